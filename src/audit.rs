@@ -0,0 +1,50 @@
+// src/audit.rs
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use std::fs::OpenOptions;
+use std::io::Write;
+
+use crate::storage::SenderRecord;
+
+/// A deletion, as recorded in the audit trail.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum AuditAction {
+    /// `DELETE /number/{key}`: the number and all its senders.
+    DeleteNumber { key: String, removed: Vec<SenderRecord> },
+    /// `DELETE /number/{key}/sender/{val}`: one sender of a number.
+    DeleteSender { key: String, removed: SenderRecord },
+}
+
+/// One line of the audit trail.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub at: DateTime<Utc>,
+    /// `auth::fingerprint` of the token that made the change.
+    pub token: String,
+    #[serde(flatten)]
+    pub action: AuditAction,
+}
+
+/// Appends `entry` as a JSON line to the audit trail at `path` and syncs it.
+///
+/// Routes call this before applying the change, so nothing is deleted
+/// without a record of it.
+pub fn record(path: &str, entry: &AuditEntry) -> std::io::Result<()> {
+    let mut line = serde_json::to_vec(entry).map_err(std::io::Error::other)?;
+    line.push(b'\n');
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(&line)?;
+    file.sync_data()
+}
+
+/// Reads every entry of the audit trail at `path`, oldest first.
+pub fn read(path: &str) -> std::io::Result<Vec<AuditEntry>> {
+    std::fs::read_to_string(path)?
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| serde_json::from_str(line).map_err(std::io::Error::other))
+        .collect()
+}
@@ -0,0 +1,380 @@
+// src/auth.rs
+
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use rand::RngCore;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+
+use crate::error::N2oError;
+
+/// The prefix that marks a tokens file entry as a salted SHA-256 hash
+/// (`sha256$<salt hex>$<hash hex>`) rather than a plaintext token.
+pub const SHA256_PREFIX: &str = "sha256$";
+
+/// Random bytes in a generated token, and in a salt.
+const TOKEN_BYTES: usize = 32;
+const SALT_BYTES: usize = 16;
+
+/// What a token may do. Each route requires one scope; `Admin` grants all
+/// of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    /// Read-only lookups: `/check`, `/sender/...`, `GET /senders`, `/normalize`
+    /// and `/status`.
+    Check,
+    /// Recording contacts: `/add`, `/addmulti`, their batch forms and `/import`.
+    Add,
+    /// Exporting everything with `/dump`.
+    Dump,
+    /// Wiping the store with `/clear`, or deleting one number or sender
+    /// with `DELETE /number`.
+    Clear,
+    /// Managing the suppression list: `/suppress`, `/unsuppress`,
+    /// `/suppress/import` and the `/inbound` webhook.
+    Suppress,
+    /// Managing the sender registry: `PUT` and `DELETE /senders/{val}`, and
+    /// enabling or disabling a sender.
+    Senders,
+    /// Every scope, including ones added later.
+    Admin,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 7] =
+        [Scope::Check, Scope::Add, Scope::Dump, Scope::Clear, Scope::Suppress, Scope::Senders, Scope::Admin];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Scope::Check => "check",
+            Scope::Add => "add",
+            Scope::Dump => "dump",
+            Scope::Clear => "clear",
+            Scope::Suppress => "suppress",
+            Scope::Senders => "senders",
+            Scope::Admin => "admin",
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Scope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Scope::ALL
+            .into_iter()
+            .find(|scope| scope.as_str() == s)
+            .ok_or_else(|| format!("unknown scope '{}'", s))
+    }
+}
+
+/// How a token is stored in the configuration.
+#[derive(Clone, PartialEq, Eq)]
+pub enum Secret {
+    /// The token itself.
+    Plain(String),
+    /// `SHA-256(salt || token)`, so the configuration does not reveal it.
+    Sha256 { salt: Vec<u8>, hash: Vec<u8> },
+}
+
+impl Secret {
+    /// Reads a configured secret: a `sha256$` hash line, or otherwise a
+    /// plaintext token.
+    pub fn parse(s: &str) -> Result<Secret, String> {
+        let rest = match s.strip_prefix(SHA256_PREFIX) {
+            Some(rest) => rest,
+            None => return Ok(Secret::Plain(s.to_string())),
+        };
+        let (salt, hash) = rest.split_once('$').ok_or("expected sha256$<salt>$<hash>")?;
+        let salt = from_hex(salt).ok_or("salt is not hex")?;
+        let hash = from_hex(hash).ok_or("hash is not hex")?;
+        if hash.len() != Sha256::output_size() {
+            return Err("hash is not a SHA-256 digest".to_string());
+        }
+        Ok(Secret::Sha256 { salt, hash })
+    }
+
+    /// Hashes `token` with a fresh random salt.
+    pub fn hash(token: &str) -> Secret {
+        let mut salt = vec![0; SALT_BYTES];
+        rand::thread_rng().fill_bytes(&mut salt);
+        let hash = salted_sha256(&salt, token);
+        Secret::Sha256 { salt, hash }
+    }
+
+    /// Whether `token` is this secret, compared in constant time.
+    pub fn matches(&self, token: &str) -> bool {
+        match self {
+            Secret::Plain(secret) => secret.as_bytes().ct_eq(token.as_bytes()).into(),
+            Secret::Sha256 { salt, hash } => salted_sha256(salt, token).ct_eq(hash).into(),
+        }
+    }
+}
+
+/// The form written to the tokens file.
+impl fmt::Display for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Secret::Plain(secret) => f.write_str(secret),
+            Secret::Sha256 { salt, hash } => write!(f, "{}{}${}", SHA256_PREFIX, to_hex(salt), to_hex(hash)),
+        }
+    }
+}
+
+// Keeps plaintext tokens out of logs
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Secret::Plain(_) => f.write_str("Plain(<redacted>)"),
+            Secret::Sha256 { .. } => write!(f, "{}", self),
+        }
+    }
+}
+
+fn salted_sha256(salt: &[u8], token: &str) -> Vec<u8> {
+    Sha256::new().chain_update(salt).chain_update(token.as_bytes()).finalize().to_vec()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len()).step_by(2).map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok()).collect()
+}
+
+/// A short, stable identifier for `token` that does not reveal it, for logs
+/// and the audit trail.
+pub fn fingerprint(token: &str) -> String {
+    to_hex(&Sha256::digest(token.as_bytes())[..6])
+}
+
+/// Creates a random token for `n2o gen-token`.
+pub fn generate_token() -> String {
+    let mut bytes = [0; TOKEN_BYTES];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    to_hex(&bytes)
+}
+
+/// A token accepted in the `Authorization` header, with its scopes and
+/// optionally the one namespace it may use.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    secret: Secret,
+    scopes: Vec<Scope>,
+    namespace: Option<String>,
+}
+
+impl Token {
+    pub fn new(secret: Secret, scopes: Vec<Scope>) -> Self {
+        Token { secret, scopes, namespace: None }
+    }
+
+    /// Restricts the token to namespace `name`.
+    pub fn bound_to(mut self, name: impl Into<String>) -> Self {
+        self.namespace = Some(name.into());
+        self
+    }
+
+    /// Parses a tokens file line: the token (or its `sha256$` hash), then
+    /// optionally a comma-separated list of scopes and an `@namespace` the
+    /// token is bound to, separated by whitespace. A token without scopes is
+    /// an `admin` token, which is what every token was before scopes existed.
+    pub fn parse(line: &str) -> Result<Token, String> {
+        let mut fields = line.split_whitespace();
+        let secret = Secret::parse(fields.next().ok_or("empty token")?)?;
+        let mut token = Token::new(secret, vec![Scope::Admin]);
+        let (mut scopes, mut namespace) = (None, None);
+        for field in fields {
+            match field.strip_prefix('@') {
+                Some(name) if namespace.is_none() => namespace = Some(name.to_string()),
+                None if scopes.is_none() => {
+                    let list = field.split(',').filter(|s| !s.is_empty()).map(str::parse).collect::<Result<_, _>>()?;
+                    scopes = Some(list);
+                }
+                _ => return Err("expected a token, a comma-separated scope list and an @namespace".to_string()),
+            }
+        }
+        if let Some(scopes) = scopes {
+            token.scopes = scopes;
+        }
+        token.namespace = namespace;
+        Ok(token)
+    }
+
+    pub fn scopes(&self) -> &[Scope] {
+        &self.scopes
+    }
+
+    /// The namespace this token is bound to, if any.
+    pub fn namespace(&self) -> Option<&str> {
+        self.namespace.as_deref()
+    }
+
+    /// Whether the configuration holds this token in plaintext.
+    pub fn is_plaintext(&self) -> bool {
+        matches!(self.secret, Secret::Plain(_))
+    }
+
+    /// Whether this token may call a route that requires `scope`.
+    pub fn allows(&self, scope: Scope) -> bool {
+        self.scopes.iter().any(|&s| s == scope || s == Scope::Admin)
+    }
+}
+
+/// Parses `VALID_TOKENS`: comma-separated tokens (or `sha256$` hashes), each
+/// an `admin` token. Scopes and namespaces can only be given in the tokens
+/// file, so an entry with whitespace is refused rather than read as one of
+/// its lines.
+pub fn parse_env_tokens(value: &str) -> Result<Vec<Token>, String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|entry| {
+            if entry.contains(char::is_whitespace) {
+                return Err(format!("'{}' contains whitespace; scoped tokens go in tokens_file", entry));
+            }
+            Ok(Token::new(Secret::parse(entry)?, vec![Scope::Admin]))
+        })
+        .collect()
+}
+
+/// A bare plaintext token is an `admin` token.
+impl From<&str> for Token {
+    fn from(secret: &str) -> Self {
+        Token::new(Secret::Plain(secret.to_string()), vec![Scope::Admin])
+    }
+}
+
+impl From<String> for Token {
+    fn from(secret: String) -> Self {
+        Token::new(Secret::Plain(secret), vec![Scope::Admin])
+    }
+}
+
+/// Checks the `Authorization` header of a request to a route that requires
+/// `scope`, returning the namespace the token is bound to, if any.
+pub fn authorize(tokens: &[Token], header: Option<&str>, scope: Scope) -> Result<Option<String>, N2oError> {
+    let header = header.ok_or(N2oError::MissingToken)?;
+    let token = tokens.iter().find(|t| t.secret.matches(header)).ok_or(N2oError::InvalidToken)?;
+    if token.allows(scope) {
+        Ok(token.namespace.clone())
+    } else {
+        Err(N2oError::InsufficientScope(scope))
+    }
+}
+
+/// The password of an HTTP Basic `Authorization` header, for webhooks whose
+/// sender can only put credentials in the URL. The user name is ignored.
+pub fn basic_password(header: &str) -> Option<String> {
+    let (scheme, encoded) = header.split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+    let decoded = base64::engine::general_purpose::STANDARD.decode(encoded.trim()).ok()?;
+    let credentials = String::from_utf8(decoded).ok()?;
+    let (_, password) = credentials.split_once(':')?;
+    Some(password.to_string())
+}
+
+/// The outcome of the most recent token reload, reported by `/status`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ReloadStatus {
+    /// Tokens currently accepted.
+    pub tokens: usize,
+    /// When the current tokens were loaded.
+    pub loaded_at: Option<DateTime<Utc>>,
+    /// Why the last reload failed; cleared by the next successful one.
+    pub last_error: Option<String>,
+    pub last_error_at: Option<DateTime<Utc>>,
+}
+
+/// The accepted tokens, shared by every route and replaced as a whole when
+/// they are reloaded, so a request sees either the old set or the new one.
+#[derive(Debug)]
+pub struct TokenSet {
+    tokens: RwLock<Arc<Vec<Token>>>,
+    status: Mutex<ReloadStatus>,
+}
+
+impl TokenSet {
+    pub fn new(tokens: Vec<Token>) -> Self {
+        let status = ReloadStatus { tokens: tokens.len(), loaded_at: Some(Utc::now()), ..Default::default() };
+        TokenSet { tokens: RwLock::new(Arc::new(tokens)), status: Mutex::new(status) }
+    }
+
+    /// The tokens accepted right now.
+    pub fn current(&self) -> Arc<Vec<Token>> {
+        Arc::clone(&self.tokens.read().unwrap())
+    }
+
+    pub fn status(&self) -> ReloadStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    /// Replaces the tokens with the result of `load`. On failure the current
+    /// tokens stay in place and the error is kept for `/status`.
+    pub fn reload(&self, load: impl FnOnce() -> Result<Vec<Token>, String>) -> Result<usize, String> {
+        let result = load();
+        let mut status = self.status.lock().unwrap();
+        match result {
+            Ok(tokens) => {
+                let count = tokens.len();
+                *self.tokens.write().unwrap() = Arc::new(tokens);
+                status.tokens = count;
+                status.loaded_at = Some(Utc::now());
+                // The file is good again, so an earlier failure no longer applies
+                status.last_error = None;
+                status.last_error_at = None;
+                log::info!("Reloaded tokens: {} accepted", count);
+                Ok(count)
+            }
+            Err(e) => {
+                log::error!("Failed to reload tokens, keeping the current ones: {}", e);
+                status.last_error = Some(e.clone());
+                status.last_error_at = Some(Utc::now());
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Reloads `tokens` whenever the modification time of `path` changes,
+/// checking every `interval`. Runs until the task is dropped.
+pub async fn watch_file(
+    tokens: Arc<TokenSet>,
+    path: impl AsRef<Path>,
+    interval: Duration,
+    load: impl Fn() -> Result<Vec<Token>, String>,
+) {
+    let modified = |path: &Path| -> Option<SystemTime> { std::fs::metadata(path).and_then(|m| m.modified()).ok() };
+    let mut last = modified(path.as_ref());
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        ticker.tick().await;
+        let now = modified(path.as_ref());
+        if now != last {
+            last = now;
+            let _ = tokens.reload(&load);
+        }
+    }
+}
@@ -0,0 +1,542 @@
+// src/config.rs
+
+use clap::{Parser, Subcommand};
+use serde::{Deserialize, Serialize};
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+use crate::auth::Token;
+use crate::inbound::InboundConfig;
+use crate::namespace::{self, NamespaceConfig, DEFAULT_NAMESPACE};
+use crate::normalize::NormalizationPolicy;
+use crate::{AUDIT_FILE, DATA_FILE, REGISTRY_FILE, SQLITE_FILE, SUPPRESSION_FILE};
+
+/// The placeholder in `archive_pattern` replaced by the archive's timestamp.
+pub const TIMESTAMP_PLACEHOLDER: &str = "{timestamp}";
+
+/// The archive filename pattern used unless configured otherwise.
+pub const DEFAULT_ARCHIVE_PATTERN: &str = "n2o_data_backup_{timestamp}.json.gz";
+
+/// The config file read when `--config` is not given, if it exists.
+pub const DEFAULT_CONFIG_FILE: &str = "n2o.toml";
+
+/// The longest accepted `cooldown_days` (100 years).
+pub const MAX_COOLDOWN_DAYS: u32 = 36_500;
+
+/// Log levels accepted by `log_level`.
+pub const LOG_LEVELS: &[&str] = &["off", "error", "warn", "info", "debug", "trace"];
+
+/// Which storage engine backs the store.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageEngine {
+    /// `JsonFileBackend`: JSON snapshot plus journal.
+    Json,
+    /// `SqliteBackend`: embedded SQLite database.
+    Sqlite,
+}
+
+/// How phone numbers are normalized before they are validated and stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum NumberFormat {
+    /// The last 10 digits, via `convert_to_ten_digits`; NANP numbers only.
+    #[value(name = "ten_digit")]
+    TenDigit,
+    /// E.164 (`+` and country code), parsed with `default_country` for
+    /// numbers written without one.
+    #[value(name = "e164")]
+    E164,
+}
+
+/// Runtime settings shared by `main` and `create_routes`.
+///
+/// Values are layered: built-in defaults, then the TOML config file, then
+/// `N2O_*` environment variables, then command-line flags.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Address the HTTP server binds to.
+    pub bind_address: String,
+    /// Port the HTTP server listens on.
+    pub port: u16,
+    /// Port to use instead when `port` is already taken.
+    pub fallback_port: Option<u16>,
+    /// Storage engine.
+    pub storage: StorageEngine,
+    /// The JSON data file (snapshot) for the `json` engine.
+    pub data_file: String,
+    /// The database file for the `sqlite` engine.
+    pub sqlite_file: String,
+    /// The suppression (do-not-contact) list, kept apart from the data.
+    pub suppression_file: String,
+    /// Append-only JSON lines log of single-number deletions.
+    pub audit_file: String,
+    /// The sender registry managed with `/senders`.
+    pub registry_file: String,
+    /// Directory `/clear` writes archives to, and startup recovery reads them from.
+    pub archive_dir: String,
+    /// Archive filename; must contain `{timestamp}`.
+    pub archive_pattern: String,
+    /// Restore the newest archive at startup if the data file is corrupt,
+    /// instead of refusing to start.
+    pub recover_from_backup: bool,
+    /// File with one token per line, read in addition to `VALID_TOKENS`.
+    pub tokens_file: Option<String>,
+    /// Seconds between checks of `tokens_file` for changes, which are then
+    /// reloaded; 0 reloads only on SIGHUP.
+    pub tokens_poll_secs: u64,
+    /// Maximum number of senders `/addmulti` records per number; 0 means unlimited.
+    pub max_senders: usize,
+    /// Days after its last contact before a number (or a number/sender pair
+    /// for `/addmulti`) may be contacted again; unset means never.
+    pub cooldown_days: Option<u32>,
+    /// Refuse `/addmulti` from senders that are not registered, are
+    /// disabled or have reached their daily cap.
+    pub strict_senders: bool,
+    /// How numbers are normalized and stored.
+    pub number_format: NumberFormat,
+    /// ISO 3166 country assumed for numbers without a country code, in
+    /// `e164` mode.
+    pub default_country: String,
+    /// One of `LOG_LEVELS`.
+    pub log_level: String,
+    /// Optional normalization steps. Kept after the plain settings so it is
+    /// written as a `[normalization]` table.
+    pub normalization: NormalizationPolicy,
+    /// Opt-out and opt-in keywords recognized by `/inbound`.
+    pub inbound: InboundConfig,
+    /// Namespaces served under `/ns/{name}` besides `default`, each with its
+    /// own store, files and limits.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub namespaces: BTreeMap<String, NamespaceConfig>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            bind_address: "0.0.0.0".to_string(),
+            port: 1337,
+            fallback_port: Some(1338),
+            storage: StorageEngine::Json,
+            data_file: DATA_FILE.to_string(),
+            sqlite_file: SQLITE_FILE.to_string(),
+            suppression_file: SUPPRESSION_FILE.to_string(),
+            audit_file: AUDIT_FILE.to_string(),
+            registry_file: REGISTRY_FILE.to_string(),
+            archive_dir: ".".to_string(),
+            archive_pattern: DEFAULT_ARCHIVE_PATTERN.to_string(),
+            recover_from_backup: false,
+            tokens_file: None,
+            tokens_poll_secs: 5,
+            max_senders: 2,
+            cooldown_days: None,
+            strict_senders: false,
+            number_format: NumberFormat::TenDigit,
+            default_country: "US".to_string(),
+            log_level: "info".to_string(),
+            normalization: NormalizationPolicy::default(),
+            inbound: InboundConfig::default(),
+            namespaces: BTreeMap::new(),
+        }
+    }
+}
+
+/// Command-line flags. Every setting can also come from its `N2O_*`
+/// environment variable; flags win over the environment.
+#[derive(Debug, Default, Parser)]
+#[command(name = "n2o", version, about = "Tracks which phone numbers have been texted, and by whom.")]
+pub struct Cli {
+    /// TOML config file [default: n2o.toml, if present]
+    #[arg(long, env = "N2O_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Address to bind to
+    #[arg(long, env = "N2O_BIND_ADDRESS")]
+    pub bind_address: Option<String>,
+
+    /// Port to listen on
+    #[arg(long, env = "N2O_PORT")]
+    pub port: Option<u16>,
+
+    /// Port to use when --port is taken
+    #[arg(long, env = "N2O_FALLBACK_PORT")]
+    pub fallback_port: Option<u16>,
+
+    /// Storage engine
+    #[arg(long, env = "N2O_STORAGE", value_enum)]
+    pub storage: Option<StorageEngine>,
+
+    /// JSON data file for the json engine
+    #[arg(long, env = "N2O_DATA_FILE")]
+    pub data_file: Option<String>,
+
+    /// Database file for the sqlite engine
+    #[arg(long, env = "N2O_SQLITE_FILE")]
+    pub sqlite_file: Option<String>,
+
+    /// Suppression (do-not-contact) list file
+    #[arg(long, env = "N2O_SUPPRESSION_FILE")]
+    pub suppression_file: Option<String>,
+
+    /// Audit trail of deletions
+    #[arg(long, env = "N2O_AUDIT_FILE")]
+    pub audit_file: Option<String>,
+
+    /// Sender registry file
+    #[arg(long, env = "N2O_REGISTRY_FILE")]
+    pub registry_file: Option<String>,
+
+    /// Directory for archives written by /clear
+    #[arg(long, env = "N2O_ARCHIVE_DIR")]
+    pub archive_dir: Option<String>,
+
+    /// Archive file name, containing {timestamp}
+    #[arg(long, env = "N2O_ARCHIVE_PATTERN")]
+    pub archive_pattern: Option<String>,
+
+    /// Restore the newest archive if the data file is corrupt (true or false;
+    /// the bare flag means true)
+    #[arg(long, env = "N2O_RECOVER_FROM_BACKUP", value_name = "BOOL", num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    pub recover_from_backup: Option<bool>,
+
+    /// File with one token per line
+    #[arg(long, env = "N2O_TOKENS_FILE")]
+    pub tokens_file: Option<String>,
+
+    /// Seconds between checks of the tokens file for changes (0 for SIGHUP only)
+    #[arg(long, env = "N2O_TOKENS_POLL_SECS")]
+    pub tokens_poll_secs: Option<u64>,
+
+    /// Maximum senders per number for /addmulti (0 for unlimited)
+    #[arg(long, env = "N2O_MAX_SENDERS")]
+    pub max_senders: Option<usize>,
+
+    /// Days before a texted number may be texted again
+    #[arg(long, env = "N2O_COOLDOWN_DAYS")]
+    pub cooldown_days: Option<u32>,
+
+    /// Only accept /addmulti from registered, active senders within their daily
+    /// cap (true or false; the bare flag means true)
+    #[arg(long, env = "N2O_STRICT_SENDERS", value_name = "BOOL", num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    pub strict_senders: Option<bool>,
+
+    /// Number normalization: ten_digit or e164
+    #[arg(long, env = "N2O_NUMBER_FORMAT", value_enum)]
+    pub number_format: Option<NumberFormat>,
+
+    /// Country for numbers without a country code in e164 mode (e.g. US, GB)
+    #[arg(long, env = "N2O_DEFAULT_COUNTRY")]
+    pub default_country: Option<String>,
+
+    /// Log level (off, error, warn, info, debug, trace)
+    #[arg(long, env = "N2O_LOG_LEVEL")]
+    pub log_level: Option<String>,
+
+    /// Translate vanity letters to digits (true or false)
+    #[arg(long, env = "N2O_VANITY_LETTERS", value_name = "BOOL")]
+    pub vanity_letters: Option<bool>,
+
+    /// Strip x/ext/# extensions (true or false)
+    #[arg(long, env = "N2O_STRIP_EXTENSIONS", value_name = "BOOL")]
+    pub strip_extensions: Option<bool>,
+
+    /// Accept 5-6 digit short codes as senders (true or false)
+    #[arg(long, env = "N2O_SHORT_CODE_SENDERS", value_name = "BOOL")]
+    pub short_code_senders: Option<bool>,
+
+    /// Comma-separated replies that opt a number out (e.g. STOP,QUIT)
+    #[arg(long, env = "N2O_OPT_OUT_KEYWORDS", value_delimiter = ',')]
+    pub opt_out_keywords: Option<Vec<String>>,
+
+    /// Comma-separated replies that opt a number back in (e.g. START)
+    #[arg(long, env = "N2O_OPT_IN_KEYWORDS", value_delimiter = ',')]
+    pub opt_in_keywords: Option<Vec<String>>,
+
+    /// Print the effective configuration as TOML and exit
+    #[arg(long)]
+    pub print_config: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Subcommands; without one, `n2o` runs the server.
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Generate a new token and print its hashed tokens file line
+    GenToken {
+        /// Comma-separated scopes for the token
+        #[arg(long, default_value = "admin")]
+        scopes: String,
+    },
+}
+
+impl Config {
+    /// Builds the effective configuration from the config file named by
+    /// `cli.config` (or `n2o.toml` if present) and the flag/env overrides.
+    pub fn load(cli: &Cli) -> Result<Config, String> {
+        let mut config = match &cli.config {
+            Some(path) => Config::from_file(path)?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => Config::from_file(Path::new(DEFAULT_CONFIG_FILE))?,
+            None => Config::default(),
+        };
+        config.apply(cli);
+        Ok(config)
+    }
+
+    /// Reads a TOML config file; missing keys keep their defaults.
+    pub fn from_file(path: &Path) -> Result<Config, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        toml::from_str(&text).map_err(|e| format!("invalid config file {}: {}", path.display(), e))
+    }
+
+    /// Overrides settings with any flags (or environment variables) that were set.
+    pub fn apply(&mut self, cli: &Cli) {
+        if let Some(v) = &cli.bind_address {
+            self.bind_address = v.clone();
+        }
+        if let Some(v) = cli.port {
+            self.port = v;
+        }
+        if let Some(v) = cli.fallback_port {
+            self.fallback_port = Some(v);
+        }
+        if let Some(v) = cli.storage {
+            self.storage = v;
+        }
+        if let Some(v) = &cli.data_file {
+            self.data_file = v.clone();
+        }
+        if let Some(v) = &cli.sqlite_file {
+            self.sqlite_file = v.clone();
+        }
+        if let Some(v) = &cli.suppression_file {
+            self.suppression_file = v.clone();
+        }
+        if let Some(v) = &cli.audit_file {
+            self.audit_file = v.clone();
+        }
+        if let Some(v) = &cli.registry_file {
+            self.registry_file = v.clone();
+        }
+        if let Some(v) = &cli.archive_dir {
+            self.archive_dir = v.clone();
+        }
+        if let Some(v) = &cli.archive_pattern {
+            self.archive_pattern = v.clone();
+        }
+        if let Some(v) = cli.recover_from_backup {
+            self.recover_from_backup = v;
+        }
+        if let Some(v) = &cli.tokens_file {
+            self.tokens_file = Some(v.clone());
+        }
+        if let Some(v) = cli.tokens_poll_secs {
+            self.tokens_poll_secs = v;
+        }
+        if let Some(v) = cli.max_senders {
+            self.max_senders = v;
+        }
+        if let Some(v) = cli.cooldown_days {
+            self.cooldown_days = Some(v);
+        }
+        if let Some(v) = cli.strict_senders {
+            self.strict_senders = v;
+        }
+        if let Some(v) = cli.number_format {
+            self.number_format = v;
+        }
+        if let Some(v) = &cli.default_country {
+            self.default_country = v.clone();
+        }
+        if let Some(v) = &cli.log_level {
+            self.log_level = v.clone();
+        }
+        if let Some(v) = cli.vanity_letters {
+            self.normalization.vanity_letters = v;
+        }
+        if let Some(v) = cli.strip_extensions {
+            self.normalization.strip_extensions = v;
+        }
+        if let Some(v) = cli.short_code_senders {
+            self.normalization.short_code_senders = v;
+        }
+        if let Some(v) = &cli.opt_out_keywords {
+            self.inbound.opt_out_keywords = v.clone();
+        }
+        if let Some(v) = &cli.opt_in_keywords {
+            self.inbound.opt_in_keywords = v.clone();
+        }
+    }
+
+    /// Checks every setting, returning all problems found rather than the first.
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors = Vec::new();
+
+        if self.bind_address.parse::<IpAddr>().is_err() {
+            errors.push(format!("bind_address '{}' is not an IP address", self.bind_address));
+        }
+        if self.port == 0 {
+            errors.push("port must be between 1 and 65535".to_string());
+        }
+        if self.fallback_port == Some(0) {
+            errors.push("fallback_port must be between 1 and 65535".to_string());
+        }
+        if self.data_file.trim().is_empty() {
+            errors.push("data_file must not be empty".to_string());
+        }
+        if self.sqlite_file.trim().is_empty() {
+            errors.push("sqlite_file must not be empty".to_string());
+        }
+        if self.suppression_file.trim().is_empty() {
+            errors.push("suppression_file must not be empty".to_string());
+        }
+        if self.audit_file.trim().is_empty() {
+            errors.push("audit_file must not be empty".to_string());
+        }
+        if self.registry_file.trim().is_empty() {
+            errors.push("registry_file must not be empty".to_string());
+        }
+        if self.archive_pattern.matches(TIMESTAMP_PLACEHOLDER).count() != 1 {
+            errors.push(format!(
+                "archive_pattern must contain {} exactly once (got '{}')",
+                TIMESTAMP_PLACEHOLDER, self.archive_pattern
+            ));
+        }
+        if self.archive_pattern.contains('/') || self.archive_pattern.contains('\\') {
+            errors.push("archive_pattern must be a file name; use archive_dir for the directory".to_string());
+        }
+        if let Some(path) = &self.tokens_file {
+            if !Path::new(path).is_file() {
+                errors.push(format!("tokens_file '{}' does not exist", path));
+            }
+        }
+        if self.cooldown_days.is_some_and(|days| days > MAX_COOLDOWN_DAYS) {
+            errors.push(format!("cooldown_days must be at most {}", MAX_COOLDOWN_DAYS));
+        }
+        if self.country().is_none() {
+            errors.push(format!(
+                "default_country '{}' is not a two-letter ISO 3166 country code",
+                self.default_country
+            ));
+        }
+        if !LOG_LEVELS.contains(&self.log_level.as_str()) {
+            errors.push(format!(
+                "log_level '{}' must be one of {}",
+                self.log_level,
+                LOG_LEVELS.join(", ")
+            ));
+        }
+        self.inbound.validate(&mut errors);
+        for (name, ns) in &self.namespaces {
+            if name == DEFAULT_NAMESPACE {
+                errors.push(format!("namespace '{}' is reserved; it uses the top-level settings", name));
+            } else if !namespace::valid_name(name) {
+                errors.push(format!(
+                    "namespace '{}' must be 1-{} lowercase letters, digits, '-' or '_'",
+                    name,
+                    namespace::MAX_NAME_LEN
+                ));
+            }
+            if ns.cooldown_days.is_some_and(|days| days > MAX_COOLDOWN_DAYS) {
+                errors.push(format!("namespaces.{}.cooldown_days must be at most {}", name, MAX_COOLDOWN_DAYS));
+            }
+        }
+        // Namespaces sharing a file would overwrite each other's data
+        let mut files = HashSet::new();
+        for name in self.namespace_names() {
+            let config = self.for_namespace(name);
+            for file in [config.data_file, config.sqlite_file, config.suppression_file, config.audit_file, config.registry_file] {
+                if !files.insert(file.clone()) {
+                    errors.push(format!("namespace '{}' uses {}, which another namespace also uses", name, file));
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// `default_country` as a country ID, if it is a known one.
+    pub fn country(&self) -> Option<phonenumber::country::Id> {
+        self.default_country.to_ascii_uppercase().parse().ok()
+    }
+
+    /// The configuration as TOML, for `--print-config`.
+    pub fn to_toml(&self) -> String {
+        toml::to_string_pretty(self).expect("Config always serializes")
+    }
+
+    /// `default` followed by the configured namespaces.
+    pub fn namespace_names(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(DEFAULT_NAMESPACE).chain(self.namespaces.keys().map(String::as_str))
+    }
+
+    pub fn has_namespace(&self, name: &str) -> bool {
+        name == DEFAULT_NAMESPACE || self.namespaces.contains_key(name)
+    }
+
+    /// The effective settings of namespace `name`: the top-level ones with
+    /// its overrides applied. Files it does not set get the namespace name
+    /// added (`n2o_data.acme.json`), and archives go to a subdirectory of
+    /// `archive_dir`.
+    pub fn for_namespace(&self, name: &str) -> Config {
+        let mut config = self.clone();
+        let ns = match self.namespaces.get(name) {
+            Some(ns) if name != DEFAULT_NAMESPACE => ns,
+            _ => return config,
+        };
+        config.data_file = ns.data_file.clone().unwrap_or_else(|| namespace::namespaced_file(&self.data_file, name));
+        config.sqlite_file = ns.sqlite_file.clone().unwrap_or_else(|| namespace::namespaced_file(&self.sqlite_file, name));
+        config.suppression_file = ns
+            .suppression_file
+            .clone()
+            .unwrap_or_else(|| namespace::namespaced_file(&self.suppression_file, name));
+        config.audit_file = ns.audit_file.clone().unwrap_or_else(|| namespace::namespaced_file(&self.audit_file, name));
+        config.registry_file = ns
+            .registry_file
+            .clone()
+            .unwrap_or_else(|| namespace::namespaced_file(&self.registry_file, name));
+        config.archive_dir = ns.archive_dir.clone().unwrap_or_else(|| {
+            Path::new(&self.archive_dir).join(name).to_string_lossy().into_owned()
+        });
+        config.max_senders = ns.max_senders.unwrap_or(self.max_senders);
+        config.cooldown_days = ns.cooldown_days.or(self.cooldown_days);
+        config.strict_senders = ns.strict_senders.unwrap_or(self.strict_senders);
+        config
+    }
+
+    /// The archive path for a given timestamp.
+    pub fn archive_path(&self, timestamp: &str) -> PathBuf {
+        PathBuf::from(&self.archive_dir).join(self.archive_pattern.replace(TIMESTAMP_PLACEHOLDER, timestamp))
+    }
+
+    /// Reads the tokens in `tokens_file`: one per line (see `Token::parse`),
+    /// blank lines and lines starting with `#` ignored.
+    pub fn file_tokens(&self) -> Result<Vec<Token>, String> {
+        let path = match &self.tokens_file {
+            Some(path) => path,
+            None => return Ok(Vec::new()),
+        };
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        text.lines()
+            .enumerate()
+            .map(|(i, line)| (i + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+            .map(|(line_no, line)| {
+                let token = Token::parse(line).and_then(|token| match token.namespace() {
+                    Some(ns) if !self.has_namespace(ns) => Err(format!("unknown namespace '{}'", ns)),
+                    _ => Ok(token),
+                });
+                token.map_err(|e| format!("{} line {}: {}", path, line_no, e))
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,180 @@
+// src/error.rs
+
+use std::convert::Infallible;
+
+use warp::http::StatusCode;
+use warp::{Rejection, Reply};
+
+use crate::auth::Scope;
+use crate::validation::ValidationError;
+
+/// Every way a request can fail. Handlers reject with one of these, and
+/// `recover` turns it (or one of warp's own rejections) into a JSON reply
+/// with a matching HTTP status.
+///
+/// Error bodies always carry `"status": "error"` (or `"exists"` for
+/// `Exists` and `"suppressed"` for `Suppressed`), so clients that only read
+/// `status` keep working.
+#[derive(Debug)]
+pub enum N2oError {
+    /// No `Authorization` header (401).
+    MissingToken,
+    /// The `Authorization` header is not a known token (403).
+    InvalidToken,
+    /// The token is valid but lacks the scope the route requires (403).
+    InsufficientScope(Scope),
+    /// The token is bound to another namespace (403).
+    NamespaceForbidden(String),
+    /// No namespace has this name (404).
+    UnknownNamespace(String),
+    /// A phone number field failed validation (400).
+    Invalid(ValidationError),
+    /// Any other malformed request (400).
+    BadRequest(String),
+    /// The number (or number/sender pair) was already texted (409); carries
+    /// the `exists` reply.
+    Exists(serde_json::Value),
+    /// The number is on the suppression list (409); carries the `suppressed`
+    /// reply.
+    Suppressed(serde_json::Value),
+    /// In strict mode, `/addmulti` from a sender that is not registered, is
+    /// disabled or has reached its daily cap (403); carries the reply.
+    SenderRefused(serde_json::Value),
+    /// A `/senders/{val}` change for a sender that is not registered (404).
+    UnknownSender(String),
+    /// `/unsuppress` of a number that is not suppressed (404).
+    NotSuppressed(String),
+    /// `DELETE /number/{key}` of a number that is not stored (404).
+    NumberNotFound(String),
+    /// `DELETE /number/{key}/sender/{val}` of a sender the number does not
+    /// have (404).
+    SenderNotFound { key: String, sender: String },
+    /// The store could not be read (500).
+    Read(String),
+    /// A change was applied in memory but could not be persisted (500).
+    Persist(String),
+    /// `/clear` could not archive the data, so nothing was cleared (500).
+    Archive(String),
+    /// A deletion could not be written to the audit trail, so nothing was
+    /// deleted (500).
+    Audit(String),
+}
+
+impl warp::reject::Reject for N2oError {}
+
+impl From<ValidationError> for N2oError {
+    fn from(error: ValidationError) -> Self {
+        N2oError::Invalid(error)
+    }
+}
+
+impl N2oError {
+    /// A failed read of the store.
+    pub(crate) fn read(error: std::io::Error) -> Self {
+        N2oError::Read(error.to_string())
+    }
+
+    /// The HTTP status this error is sent with.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            N2oError::MissingToken => StatusCode::UNAUTHORIZED,
+            N2oError::InvalidToken
+            | N2oError::InsufficientScope(_)
+            | N2oError::NamespaceForbidden(_)
+            | N2oError::SenderRefused(_) => StatusCode::FORBIDDEN,
+            N2oError::UnknownNamespace(_)
+            | N2oError::UnknownSender(_)
+            | N2oError::NotSuppressed(_)
+            | N2oError::NumberNotFound(_)
+            | N2oError::SenderNotFound { .. } => StatusCode::NOT_FOUND,
+            N2oError::Invalid(_) | N2oError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            N2oError::Exists(_) | N2oError::Suppressed(_) => StatusCode::CONFLICT,
+            N2oError::Read(_) | N2oError::Persist(_) | N2oError::Archive(_) | N2oError::Audit(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+
+    /// The reply body.
+    pub fn to_json(&self) -> serde_json::Value {
+        let error = |code: &str, message: String| {
+            serde_json::json!({
+                "status": "error",
+                "code": code,
+                "message": message
+            })
+        };
+        match self {
+            N2oError::MissingToken => error("missing_token", "Missing Authorization header".to_string()),
+            N2oError::InvalidToken => error("invalid_token", "Invalid token".to_string()),
+            N2oError::InsufficientScope(scope) => {
+                error("insufficient_scope", format!("This token does not have the '{}' scope", scope))
+            }
+            N2oError::NamespaceForbidden(name) => {
+                error("namespace_forbidden", format!("This token may not use namespace '{}'", name))
+            }
+            N2oError::UnknownNamespace(name) => error("unknown_namespace", format!("Unknown namespace '{}'", name)),
+            N2oError::Invalid(e) => e.to_json(),
+            N2oError::BadRequest(message) => error("bad_request", message.clone()),
+            N2oError::Exists(reply) | N2oError::Suppressed(reply) | N2oError::SenderRefused(reply) => reply.clone(),
+            N2oError::UnknownSender(sender) => error("unknown_sender", format!("Sender {} is not registered", sender)),
+            N2oError::NotSuppressed(key) => error("not_suppressed", format!("{} is not suppressed", key)),
+            N2oError::NumberNotFound(key) => error("number_not_found", format!("{} is not stored", key)),
+            N2oError::SenderNotFound { key, sender } => {
+                error("sender_not_found", format!("{} has no sender {}", key, sender))
+            }
+            N2oError::Read(e) => error("read_failed", format!("Failed to read data: {}", e)),
+            N2oError::Persist(e) => error("persist_failed", format!("Failed to persist data: {}", e)),
+            N2oError::Archive(e) => error("archive_failed", format!("Failed to archive data before clearing: {}", e)),
+            N2oError::Audit(e) => error("audit_failed", format!("Failed to write the audit trail: {}", e)),
+        }
+    }
+}
+
+/// Lifts a handler's result into warp, rejecting with the error so that
+/// `recover` can reply to it.
+pub(crate) async fn reject<T>(result: Result<T, N2oError>) -> Result<T, Rejection> {
+    result.map_err(warp::reject::custom)
+}
+
+/// Replies to a rejection with the same JSON error body as `N2oError`.
+///
+/// Warp's own rejections (unknown path, wrong method, unreadable body, ...)
+/// keep their usual status codes.
+pub async fn recover(rejection: Rejection) -> Result<warp::reply::Response, Infallible> {
+    if let Some(error) = rejection.find::<N2oError>() {
+        return Ok(json_reply(&error.to_json(), error.status()));
+    }
+
+    let (status, code, message) = if rejection.is_not_found() {
+        (StatusCode::NOT_FOUND, "not_found", "Not found".to_string())
+    } else if let Some(e) = rejection.find::<warp::reject::MethodNotAllowed>() {
+        (StatusCode::METHOD_NOT_ALLOWED, "method_not_allowed", e.to_string())
+    } else if let Some(e) = rejection.find::<warp::reject::PayloadTooLarge>() {
+        (StatusCode::PAYLOAD_TOO_LARGE, "payload_too_large", e.to_string())
+    } else if let Some(e) = rejection.find::<warp::reject::UnsupportedMediaType>() {
+        (StatusCode::UNSUPPORTED_MEDIA_TYPE, "unsupported_media_type", e.to_string())
+    } else if let Some(e) = rejection.find::<warp::reject::LengthRequired>() {
+        (StatusCode::LENGTH_REQUIRED, "length_required", e.to_string())
+    } else if let Some(e) = rejection.find::<warp::filters::body::BodyDeserializeError>() {
+        (StatusCode::BAD_REQUEST, "bad_request", e.to_string())
+    } else if let Some(e) = rejection.find::<warp::reject::InvalidQuery>() {
+        (StatusCode::BAD_REQUEST, "bad_request", e.to_string())
+    } else if let Some(e) = rejection.find::<warp::reject::InvalidHeader>() {
+        (StatusCode::BAD_REQUEST, "bad_request", e.to_string())
+    } else {
+        log::error!("Unhandled rejection: {:?}", rejection);
+        (StatusCode::INTERNAL_SERVER_ERROR, "internal_error", "Internal server error".to_string())
+    };
+
+    let body = serde_json::json!({
+        "status": "error",
+        "code": code,
+        "message": message
+    });
+    Ok(json_reply(&body, status))
+}
+
+fn json_reply(body: &serde_json::Value, status: StatusCode) -> warp::reply::Response {
+    warp::reply::with_status(warp::reply::json(body), status).into_response()
+}
@@ -0,0 +1,266 @@
+// src/import.rs
+
+use serde::Serialize;
+
+/// The column holding the phone number in an imported CSV, unless the
+/// request names another.
+pub const DEFAULT_KEY_COLUMN: &str = "phone_number";
+
+/// The column holding the sender in an imported CSV, unless the request
+/// names another.
+pub const DEFAULT_VAL_COLUMN: &str = "sender";
+
+/// The optional column holding the opt-out reason in a CSV imported by
+/// `/suppress/import`, unless the request names another.
+pub const DEFAULT_REASON_COLUMN: &str = "reason";
+
+/// The body formats `/import` understands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportFormat {
+    /// CSV with a header row.
+    Csv,
+    /// One `{"key": ..., "val": ...}` object per line.
+    Ndjson,
+}
+
+impl ImportFormat {
+    /// Picks the format from the `format` query parameter, falling back to
+    /// the request's content type, then CSV.
+    pub fn detect(format: Option<&str>, content_type: Option<&str>) -> Result<Self, String> {
+        match format {
+            Some("csv") => Ok(ImportFormat::Csv),
+            Some("ndjson") => Ok(ImportFormat::Ndjson),
+            Some(other) => Err(format!("Unknown import format '{}'; expected csv or ndjson.", other)),
+            None => match content_type {
+                Some(ct) if ct.contains("ndjson") || ct.contains("jsonl") => Ok(ImportFormat::Ndjson),
+                _ => Ok(ImportFormat::Csv),
+            },
+        }
+    }
+}
+
+/// One row of an import, as it appeared in the body (not yet normalized).
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportRow {
+    /// 1-based line (CSV record or NDJSON line) the row came from.
+    pub line: usize,
+    pub key: String,
+    /// The sender, or for `suppression_rows` the reason (empty if absent).
+    pub val: String,
+}
+
+/// A row that was not imported, for the rejects report.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Rejected {
+    pub line: usize,
+    pub key: String,
+    pub val: String,
+    pub reason: String,
+}
+
+impl Rejected {
+    /// A row that could not be read at all.
+    fn unreadable(line: usize, reason: impl Into<String>) -> Self {
+        Rejected { line, key: String::new(), val: String::new(), reason: reason.into() }
+    }
+}
+
+/// Parses an import body as it arrives, so a large upload is never held in
+/// memory at once.
+///
+/// CSV bodies must have a header naming the key and val columns; a missing
+/// column fails the whole import. Rows that cannot be read are yielded as
+/// `Rejected` and do not stop the import.
+#[derive(Debug)]
+pub struct RowParser {
+    format: ImportFormat,
+    key_column: String,
+    val_column: String,
+    val_required: bool,
+    /// Bytes received after the last complete row.
+    pending: Vec<u8>,
+    /// For CSV, the key and val positions once the header has been read.
+    columns: Option<(usize, Option<usize>)>,
+    /// CSV records after the header, or NDJSON lines, consumed so far.
+    consumed: usize,
+}
+
+impl RowParser {
+    /// A parser for `/import`, where both columns are required.
+    pub fn new(format: ImportFormat, key_column: &str, val_column: &str) -> Self {
+        RowParser::with_columns(format, key_column, val_column, "val", true)
+    }
+
+    /// A parser for `/suppress/import`: the second column (`reason_column`,
+    /// or `reason` in NDJSON) is optional.
+    pub fn suppressions(format: ImportFormat, key_column: &str, reason_column: &str) -> Self {
+        RowParser::with_columns(format, key_column, reason_column, "reason", false)
+    }
+
+    /// NDJSON rows always use `key` and `val_field`; the column names only
+    /// apply to CSV.
+    fn with_columns(format: ImportFormat, key_column: &str, val_column: &str, val_field: &str, val_required: bool) -> Self {
+        let val_column = match format {
+            ImportFormat::Csv => val_column,
+            ImportFormat::Ndjson => val_field,
+        };
+        RowParser {
+            format,
+            key_column: key_column.to_string(),
+            val_column: val_column.to_string(),
+            val_required,
+            pending: Vec::new(),
+            columns: None,
+            consumed: 0,
+        }
+    }
+
+    /// Adds the next piece of the body, returning the rows it completes.
+    pub fn feed(&mut self, chunk: &[u8]) -> Result<Vec<Result<ImportRow, Rejected>>, String> {
+        self.pending.extend_from_slice(chunk);
+        match self.complete_len() {
+            Some(len) => {
+                let segment: Vec<u8> = self.pending.drain(..len).collect();
+                self.parse(&segment)
+            }
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Ends the body, returning the rows left in it.
+    pub fn finish(mut self) -> Result<Vec<Result<ImportRow, Rejected>>, String> {
+        let segment = std::mem::take(&mut self.pending);
+        self.parse(&segment)
+    }
+
+    /// The length of the pending bytes that hold only complete rows: up to
+    /// the last newline, which for CSV must not be inside a quoted field.
+    fn complete_len(&self) -> Option<usize> {
+        match self.format {
+            ImportFormat::Ndjson => self.pending.iter().rposition(|&b| b == b'\n').map(|i| i + 1),
+            ImportFormat::Csv => {
+                let mut in_quotes = false;
+                let mut end = None;
+                for (i, &b) in self.pending.iter().enumerate() {
+                    match b {
+                        b'"' => in_quotes = !in_quotes,
+                        b'\n' if !in_quotes => end = Some(i + 1),
+                        _ => {}
+                    }
+                }
+                end
+            }
+        }
+    }
+
+    fn parse(&mut self, segment: &[u8]) -> Result<Vec<Result<ImportRow, Rejected>>, String> {
+        match self.format {
+            ImportFormat::Csv => self.csv_rows(segment),
+            ImportFormat::Ndjson => Ok(self.ndjson_rows(segment)),
+        }
+    }
+
+    fn csv_rows(&mut self, segment: &[u8]) -> Result<Vec<Result<ImportRow, Rejected>>, String> {
+        let mut reader = csv::ReaderBuilder::new()
+            .flexible(true)
+            .trim(csv::Trim::All)
+            .has_headers(false)
+            .from_reader(segment);
+        let mut records = reader.records();
+        let (key_idx, val_idx) = match self.columns {
+            Some(columns) => columns,
+            None => {
+                let headers = match records.next() {
+                    Some(header) => header.map_err(|e| format!("Could not read CSV header: {}", e))?,
+                    None => csv::StringRecord::new(),
+                };
+                let column = |name: &str| {
+                    headers
+                        .iter()
+                        .position(|h| h.eq_ignore_ascii_case(name))
+                        .ok_or_else(|| format!("CSV header has no '{}' column.", name))
+                };
+                let key_idx = column(&self.key_column)?;
+                let val_idx = match column(&self.val_column) {
+                    Ok(idx) => Some(idx),
+                    Err(_) if !self.val_required => None,
+                    Err(e) => return Err(e),
+                };
+                *self.columns.insert((key_idx, val_idx))
+            }
+        };
+
+        let mut rows = Vec::new();
+        for record in records {
+            self.consumed += 1;
+            // The header is line 1
+            let line = self.consumed + 1;
+            let row = record.map_err(|e| Rejected::unreadable(line, e.to_string())).and_then(|record| {
+                let val = match val_idx {
+                    Some(idx) => record.get(idx),
+                    None => Some(""),
+                };
+                match (record.get(key_idx), val) {
+                    (Some(key), Some(val)) => Ok(ImportRow { line, key: key.to_string(), val: val.to_string() }),
+                    _ => Err(Rejected::unreadable(line, "Missing column")),
+                }
+            });
+            rows.push(row);
+        }
+        Ok(rows)
+    }
+
+    fn ndjson_rows(&mut self, segment: &[u8]) -> Vec<Result<ImportRow, Rejected>> {
+        let segment = segment.strip_suffix(b"\n").unwrap_or(segment);
+        if segment.is_empty() {
+            return Vec::new();
+        }
+        let mut rows = Vec::new();
+        for line in segment.split(|&b| b == b'\n') {
+            self.consumed += 1;
+            if line.iter().all(u8::is_ascii_whitespace) {
+                continue;
+            }
+            let line_no = self.consumed;
+            let val_field = self.val_column.as_str();
+            let row = serde_json::from_slice::<serde_json::Value>(line)
+                .map_err(|e| Rejected::unreadable(line_no, e.to_string()))
+                .and_then(|value| {
+                    let val = match &value[val_field] {
+                        serde_json::Value::Null if !self.val_required => Some(""),
+                        val => val.as_str(),
+                    };
+                    match (value["key"].as_str(), val) {
+                        (Some(key), Some(val)) => Ok(ImportRow { line: line_no, key: key.to_string(), val: val.to_string() }),
+                        _ => Err(Rejected::unreadable(line_no, format!("Expected an object with string key and {}", val_field))),
+                    }
+                });
+            rows.push(row);
+        }
+        rows
+    }
+}
+
+/// Reads the rows of a whole `/suppress/import` body; see
+/// `RowParser::suppressions`.
+pub fn suppression_rows(
+    body: &[u8],
+    format: ImportFormat,
+    key_column: &str,
+    reason_column: &str,
+) -> Result<Vec<Result<ImportRow, Rejected>>, String> {
+    let mut parser = RowParser::suppressions(format, key_column, reason_column);
+    let mut rows = parser.feed(body)?;
+    rows.extend(parser.finish()?);
+    Ok(rows)
+}
+
+/// Renders rejected rows as CSV (`line,key,val,reason`).
+pub fn rejects_csv(rejects: &[Rejected]) -> String {
+    let mut writer = csv::WriterBuilder::new().has_headers(false).from_writer(Vec::new());
+    writer.write_record(["line", "key", "val", "reason"]).expect("writing CSV to memory");
+    for reject in rejects {
+        writer.serialize(reject).expect("writing CSV to memory");
+    }
+    String::from_utf8(writer.into_inner().expect("flushing CSV to memory")).expect("CSV of UTF-8 fields")
+}
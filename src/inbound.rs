@@ -0,0 +1,102 @@
+// src/inbound.rs
+
+use serde::{Deserialize, Serialize};
+
+/// Fields that may hold the sender of an inbound message, checked in order
+/// and compared case-insensitively (`From`, `msisdn`, ...).
+pub const FROM_FIELDS: &[&str] = &["from", "msisdn", "sender"];
+
+/// Fields that may hold the text of an inbound message.
+pub const TEXT_FIELDS: &[&str] = &["body", "text", "message"];
+
+/// The keywords `/inbound` acts on, from the `[inbound]` table.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InboundConfig {
+    /// Replies that add the sender to the suppression list.
+    pub opt_out_keywords: Vec<String>,
+    /// Replies that remove the sender from the suppression list.
+    pub opt_in_keywords: Vec<String>,
+}
+
+impl Default for InboundConfig {
+    fn default() -> Self {
+        let words = |list: &[&str]| list.iter().map(|w| w.to_string()).collect();
+        InboundConfig {
+            opt_out_keywords: words(&["STOP", "UNSUBSCRIBE", "CANCEL", "QUIT"]),
+            opt_in_keywords: words(&["START", "UNSTOP"]),
+        }
+    }
+}
+
+/// What an inbound message asks for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    OptOut,
+    OptIn,
+}
+
+impl InboundConfig {
+    /// The action requested by a message and the keyword that requested it.
+    /// The keyword must be the first word, in any case, optionally followed
+    /// by punctuation (`Stop.`, `STOP please`).
+    pub fn action(&self, text: &str) -> Option<(Action, String)> {
+        let first = text.split_whitespace().next()?;
+        let word = first.trim_matches(|c: char| !c.is_alphanumeric());
+        let find = |keywords: &[String]| keywords.iter().find(|k| k.eq_ignore_ascii_case(word)).cloned();
+        find(&self.opt_out_keywords)
+            .map(|k| (Action::OptOut, k))
+            .or_else(|| find(&self.opt_in_keywords).map(|k| (Action::OptIn, k)))
+    }
+
+    /// Problems with the keyword lists, for `Config::validate`.
+    pub fn validate(&self, errors: &mut Vec<String>) {
+        for (name, keywords) in [("opt_out_keywords", &self.opt_out_keywords), ("opt_in_keywords", &self.opt_in_keywords)] {
+            for keyword in keywords {
+                if keyword.is_empty() || !keyword.chars().all(char::is_alphanumeric) {
+                    errors.push(format!("inbound.{} entry '{}' must be a single word", name, keyword));
+                }
+            }
+        }
+        for keyword in &self.opt_out_keywords {
+            if self.opt_in_keywords.iter().any(|k| k.eq_ignore_ascii_case(keyword)) {
+                errors.push(format!("inbound keyword '{}' cannot be both opt-out and opt-in", keyword));
+            }
+        }
+    }
+}
+
+/// An inbound SMS, as posted by a provider's webhook.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InboundMessage {
+    /// The sender as given, not yet normalized.
+    pub from: String,
+    pub text: String,
+}
+
+impl InboundMessage {
+    /// Reads a webhook body: form-encoded when the content type says so,
+    /// otherwise a JSON object. Field names follow `FROM_FIELDS` and
+    /// `TEXT_FIELDS`, so most providers' payloads work unchanged.
+    pub fn parse(body: &[u8], content_type: Option<&str>) -> Result<InboundMessage, String> {
+        let fields: Vec<(String, String)> = if content_type.is_some_and(|ct| ct.contains("x-www-form-urlencoded")) {
+            serde_urlencoded::from_bytes(body).map_err(|e| format!("Invalid form body: {}", e))?
+        } else {
+            let value: serde_json::Value =
+                serde_json::from_slice(body).map_err(|e| format!("Invalid JSON body: {}", e))?;
+            let object = value.as_object().ok_or("Expected a JSON object.")?;
+            object
+                .iter()
+                .filter_map(|(k, v)| Some((k.clone(), v.as_str()?.to_string())))
+                .collect()
+        };
+
+        let field = |names: &[&str]| {
+            names.iter().find_map(|name| fields.iter().find(|(k, _)| k.eq_ignore_ascii_case(name)).map(|(_, v)| v.clone()))
+        };
+        let from = field(FROM_FIELDS).ok_or("Missing sender field (from, msisdn or sender).")?;
+        let text = field(TEXT_FIELDS).ok_or("Missing text field (body, text or message).")?;
+        Ok(InboundMessage { from, text })
+    }
+}
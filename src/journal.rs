@@ -0,0 +1,163 @@
+// src/journal.rs
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::storage::{record_contact, SenderRecord};
+
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+
+/// A single mutation recorded in the append-only journal.
+///
+/// Every event is idempotent when replayed on top of a snapshot that may
+/// already contain it, which is what lets compaction write the snapshot
+/// before truncating the journal.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum JournalEvent {
+    /// A key was inserted (or replaced) with the given senders.
+    Insert { key: String, senders: Vec<SenderRecord> },
+    /// A sender contacted a key. Journals written before timestamps were
+    /// tracked have no `at`; those events are dated to the Unix epoch, like
+    /// the bare senders of old snapshots, so they replay the same way on
+    /// every open.
+    AddSender {
+        key: String,
+        sender: String,
+        #[serde(default = "unix_epoch")]
+        at: DateTime<Utc>,
+    },
+    /// A key was removed.
+    Remove { key: String },
+    /// Every key was removed.
+    Clear,
+}
+
+fn unix_epoch() -> DateTime<Utc> {
+    DateTime::UNIX_EPOCH
+}
+
+impl JournalEvent {
+    /// Applies this event to an in-memory map.
+    pub fn apply(&self, data: &mut HashMap<String, Vec<SenderRecord>>) {
+        match self {
+            JournalEvent::Insert { key, senders } => {
+                data.insert(key.clone(), senders.clone());
+            }
+            JournalEvent::AddSender { key, sender, at } => {
+                let senders = data.entry(key.clone()).or_default();
+                // A record already at or past `at` has seen this event
+                let applied = senders.iter().any(|r| &r.sender == sender && r.last_seen >= *at);
+                if !applied {
+                    record_contact(senders, sender.clone(), *at);
+                }
+            }
+            JournalEvent::Remove { key } => {
+                data.remove(key);
+            }
+            JournalEvent::Clear => data.clear(),
+        }
+    }
+}
+
+/// Returns the journal path that belongs to a given snapshot file.
+pub fn journal_path(file_path: &str) -> String {
+    format!("{}.wal", file_path)
+}
+
+/// Appends events to the journal as one JSON object per line and syncs it.
+///
+/// A failed append is cut back off, so that the partial line it may have
+/// written does not end up in the middle of the journal once the events are
+/// appended again.
+pub fn append_events(journal: &str, events: &[JournalEvent]) -> std::io::Result<()> {
+    let mut buf = Vec::new();
+    for event in events {
+        serde_json::to_writer(&mut buf, event).map_err(std::io::Error::other)?;
+        buf.push(b'\n');
+    }
+
+    let mut file = OpenOptions::new().create(true).append(true).open(journal)?;
+    let len = file.metadata()?.len();
+    let result = file.write_all(&buf).and_then(|_| file.sync_data());
+    if result.is_err() {
+        if let Err(e) = file.set_len(len) {
+            log::error!("Failed to cut a failed append off {}: {}", journal, e);
+        }
+    }
+    result
+}
+
+/// Replays the journal on top of `data`, returning how many events were applied.
+///
+/// Appends are synced, so a crash can only tear the last line: a final line
+/// without its newline is ignored, as it was never acknowledged. Any other
+/// line that does not parse is corruption and fails the replay, since
+/// skipping it would silently lose the events after it.
+pub fn replay(journal: &str, data: &mut HashMap<String, Vec<SenderRecord>>) -> std::io::Result<usize> {
+    Ok(replay_prefix(journal, data)?.0)
+}
+
+/// Replays the journal like `replay`, then cuts off a torn record at its end
+/// so that later appends are not hidden behind it.
+pub fn recover(journal: &str, data: &mut HashMap<String, Vec<SenderRecord>>) -> std::io::Result<usize> {
+    let (applied, valid_len) = replay_prefix(journal, data)?;
+    if let Ok(meta) = fs::metadata(journal) {
+        if meta.len() > valid_len {
+            log::warn!("Discarding torn record at the end of {}", journal);
+            let truncated = OpenOptions::new()
+                .write(true)
+                .open(journal)
+                .and_then(|file| file.set_len(valid_len));
+            if let Err(e) = truncated {
+                log::error!("Failed to truncate {}: {}", journal, e);
+            }
+        }
+    }
+    Ok(applied)
+}
+
+/// Applies every complete event, returning the count and the byte length of
+/// the complete lines. Fails on a complete line that does not parse.
+fn replay_prefix(journal: &str, data: &mut HashMap<String, Vec<SenderRecord>>) -> std::io::Result<(usize, u64)> {
+    let file = match fs::File::open(journal) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok((0, 0)),
+        Err(e) => return Err(e),
+    };
+
+    let mut reader = BufReader::new(file);
+    let mut line = Vec::new();
+    let mut applied = 0;
+    let mut valid_len = 0;
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        // Only the last line can lack its newline; it is a torn append
+        if line.last() != Some(&b'\n') {
+            break;
+        }
+        let event = serde_json::from_slice::<JournalEvent>(&line).map_err(|e| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{} is corrupt at line {}: {}", journal, applied + 1, e),
+            )
+        })?;
+        event.apply(data);
+        applied += 1;
+        valid_len += line.len() as u64;
+    }
+    Ok((applied, valid_len))
+}
+
+/// Empties the journal once its events are covered by a snapshot.
+pub fn truncate(journal: &str) -> std::io::Result<()> {
+    match fs::remove_file(journal) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
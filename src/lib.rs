@@ -1,60 +1,714 @@
 // src/lib.rs
 
+use futures_util::StreamExt;
 use warp::Filter;
-use serde::{Deserialize, Serialize};
 use flate2::write::GzEncoder;
 use flate2::Compression;
-use chrono::Local;
+use chrono::{DateTime, Local, SecondsFormat, TimeDelta, Utc};
+use serde::Serialize;
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fs;
 use std::io::Write;
 use std::sync::{Arc, Mutex};
 use std::time::Instant;
 
-/// A global constant for the data file path.
+pub mod audit;
+pub mod auth;
+pub mod config;
+pub mod error;
+pub mod import;
+pub mod inbound;
+pub mod journal;
+pub mod namespace;
+pub mod normalize;
+pub mod registry;
+pub mod sqlite;
+pub mod storage;
+pub mod suppression;
+pub mod validation;
+
+pub use auth::{Scope, Token, TokenSet};
+pub use config::Config;
+pub use error::N2oError;
+pub use namespace::Namespaces;
+pub use registry::{RegisteredSender, Registry, SenderRegistry};
+pub use sqlite::SqliteBackend;
+pub use storage::{load_data, save_data, JsonFileBackend, MemoryBackend, SenderRecord, StorageBackend};
+pub use suppression::{SuppressionList, SuppressionSource, Suppressions};
+use storage::PersistData;
+use normalize::Role;
+use validation::ValidationError;
+
+/// The default data file path (see `Config::data_file`).
 pub const DATA_FILE: &str = "n2o_data.json";
 
-/// Our in-memory store type used by all endpoints.
-pub type Store = Arc<Mutex<HashMap<String, Vec<String>>>>;
+/// The default database path when the SQLite engine is selected.
+pub const SQLITE_FILE: &str = "n2o_data.sqlite3";
+
+/// The default suppression list path (see `Config::suppression_file`).
+pub const SUPPRESSION_FILE: &str = "n2o_suppressed.json";
+
+/// The default audit trail path (see `Config::audit_file`).
+pub const AUDIT_FILE: &str = "n2o_audit.jsonl";
+
+/// The default sender registry path (see `Config::registry_file`).
+pub const REGISTRY_FILE: &str = "n2o_senders.json";
+
+/// The largest body `/import` (or `/suppress/import`) accepts, in bytes.
+pub const IMPORT_MAX_BYTES: u64 = 64 * 1024 * 1024;
+
+/// The largest body `/inbound` accepts, in bytes.
+pub const INBOUND_MAX_BYTES: u64 = 64 * 1024;
+
+/// Numbers per page of `/sender/{val}/numbers`, unless `limit` says otherwise.
+pub const DEFAULT_PAGE_SIZE: usize = 100;
 
-/// Archives the current data to a compressed file with a timestamp.
+/// The largest `limit` `/sender/{val}/numbers` accepts.
+pub const MAX_PAGE_SIZE: usize = 1000;
+
+/// Our shared store type used by all endpoints, generic over the storage engine.
+pub type Store<B = JsonFileBackend> = Arc<Mutex<B>>;
+
+/// Archives the given data to a compressed file with a timestamp, named
+/// after `config.archive_pattern` inside `config.archive_dir`.
 ///
 /// This is a private helper (not tested directly) but used internally
 /// by the `/clear` endpoint.
-fn archive_data(file_path: &str) -> std::io::Result<()> {
-    // Load current data
-    let data = load_data(file_path);
-
-    if data.is_empty() {
+fn archive_data<B: StorageBackend>(db: &B, config: &Config) -> std::io::Result<()> {
+    if db.is_empty()? {
         // Nothing to archive
         return Ok(());
     }
 
     // Serialize data to JSON
-    let json_data = serde_json::to_string_pretty(&PersistData(data.clone()))
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    let data: HashMap<String, Vec<SenderRecord>> = db.iter()?.collect();
+    let json_data = serde_json::to_string_pretty(&PersistData(data))
+        .map_err(std::io::Error::other)?;
 
     // Get current timestamp
     let timestamp = Local::now().format("%Y%m%d%H%M%S").to_string();
 
     // Define archive filename
-    let archive_filename = format!("n2o_data_backup_{}.json.gz", timestamp);
+    let archive_filename = config.archive_path(&timestamp);
 
     // Create the compressed file
+    fs::create_dir_all(&config.archive_dir)?;
     let file = fs::File::create(&archive_filename)?;
     let mut encoder = GzEncoder::new(file, Compression::default());
     encoder.write_all(json_data.as_bytes())?;
-    encoder.finish()?;
+    encoder.finish()?.sync_all()?;
 
-    println!("Archived data to {}", archive_filename);
+    log::info!("Archived data to {}", archive_filename.display());
     Ok(())
 }
 
+/// A file of a namespace whose flushes `PersistHealth` tracks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum PersistTarget {
+    Store,
+    Suppressions,
+    Registry,
+}
+
+/// The outcome of the most recent flushes, reported by `/status`.
+#[derive(Default)]
+struct PersistHealth {
+    last_error: Option<String>,
+    last_error_at: Option<String>,
+    /// The files whose most recent flush failed. Each is cleared only by a
+    /// successful flush of the same file.
+    failing: BTreeSet<PersistTarget>,
+}
+
+/// Everything a route needs from the namespace a request is for.
+///
+/// Routes that use several of them lock `suppressions`, then `registry`,
+/// then `store`.
+struct Tenant<B> {
+    name: String,
+    store: Store<B>,
+    suppressions: Suppressions,
+    registry: Registry,
+    health: Arc<Mutex<PersistHealth>>,
+    config: Arc<Config>,
+}
+
+impl<B> Clone for Tenant<B> {
+    fn clone(&self) -> Self {
+        Tenant {
+            name: self.name.clone(),
+            store: Arc::clone(&self.store),
+            suppressions: Arc::clone(&self.suppressions),
+            registry: Arc::clone(&self.registry),
+            health: Arc::clone(&self.health),
+            config: Arc::clone(&self.config),
+        }
+    }
+}
+
+/// Picks the namespace for a request from its `/ns/{name}` prefix and the
+/// namespace its token is bound to. A bound token may only use its own
+/// namespace; without either, the request is for `default`.
+fn resolve_tenant<B>(
+    tenants: &BTreeMap<String, Tenant<B>>,
+    path: Option<&str>,
+    bound: Option<&str>,
+) -> Result<Tenant<B>, N2oError> {
+    let name = match (path, bound) {
+        (Some(path), Some(bound)) if path != bound => return Err(N2oError::NamespaceForbidden(path.to_string())),
+        (path, bound) => path.or(bound).unwrap_or(namespace::DEFAULT_NAMESPACE),
+    };
+    tenants.get(name).cloned().ok_or_else(|| N2oError::UnknownNamespace(name.to_string()))
+}
+
+/// Persists pending changes, recording the outcome for `/status`.
+///
+/// On failure the error message is returned to the caller. What happens to
+/// the change depends on the engine: `JsonFileBackend` keeps it in memory and
+/// pending, so the next flush retries it, while `SqliteBackend` rolls its
+/// transaction back and the change is lost.
+fn persist<B: StorageBackend>(db: &mut B, health: &Mutex<PersistHealth>) -> Result<(), String> {
+    record_flush(PersistTarget::Store, db.flush(), health)
+}
+
+/// Records the outcome of a flush of `target` for `/status`, logging a
+/// failure.
+fn record_flush(target: PersistTarget, result: std::io::Result<()>, health: &Mutex<PersistHealth>) -> Result<(), String> {
+    let result = result.map_err(|e| e.to_string());
+    let mut health = health.lock().unwrap();
+    if result.is_ok() {
+        health.failing.remove(&target);
+    } else {
+        health.failing.insert(target);
+    }
+    if let Err(e) = &result {
+        log::error!("Failed to persist data: {}", e);
+        health.last_error = Some(e.clone());
+        health.last_error_at = Some(Local::now().to_rfc3339());
+    }
+    result
+}
+
+/// Persists an accepted `/add` or `/addmulti` and replies with its verdict;
+/// a refused or suppressed one is a 409, and a refused sender a 403.
+fn verdict_reply<B: StorageBackend>(
+    db: &mut B,
+    health: &Mutex<PersistHealth>,
+    verdict: Verdict,
+) -> Result<warp::reply::Json, N2oError> {
+    match verdict {
+        Verdict::Accept(_) => {
+            persist(db, health).map_err(N2oError::Persist)?;
+            Ok(warp::reply::json(&verdict.to_json()))
+        }
+        Verdict::Refuse(reply) => Err(N2oError::Exists(reply)),
+        Verdict::Suppressed(reply) => Err(N2oError::Suppressed(reply)),
+        Verdict::SenderRefused(reply) => Err(N2oError::SenderRefused(reply)),
+    }
+}
+
+/// Returns `true` if a number with `count` senders cannot take another one.
+/// A `limit` of 0 means unlimited.
+fn sender_limit_reached(count: usize, limit: usize) -> bool {
+    limit != 0 && count >= limit
+}
+
+/// How much longer a contact at `last_seen` blocks another one at `now`, or
+/// `None` once it no longer does. Without a cooldown a contact blocks forever,
+/// reported as `TimeDelta::MAX`.
+fn cooldown_remaining(last_seen: DateTime<Utc>, cooldown_days: Option<u32>, now: DateTime<Utc>) -> Option<TimeDelta> {
+    let days = match cooldown_days {
+        Some(days) => days,
+        None => return Some(TimeDelta::MAX),
+    };
+    let remaining = last_seen + TimeDelta::days(days.into()) - now;
+    (remaining > TimeDelta::zero()).then_some(remaining)
+}
+
+/// The `cooldown_remaining_seconds` reported with an `exists` reply: `null`
+/// when the number can never be re-contacted.
+fn remaining_seconds(remaining: TimeDelta) -> Option<i64> {
+    (remaining != TimeDelta::MAX).then(|| remaining.num_seconds().max(1))
+}
+
+/// What `/add` or `/addmulti` would do with a request, decided without
+/// touching the store: the routes act on it, and `/check` only reports it.
+enum Verdict {
+    /// The contact would be recorded; carries the reply message.
+    Accept(&'static str),
+    /// The contact would be refused; carries the reply.
+    Refuse(serde_json::Value),
+    /// The number is on the suppression list, so no sender may contact it;
+    /// carries the reply.
+    Suppressed(serde_json::Value),
+    /// In strict mode, the sender may not send; carries the reply.
+    SenderRefused(serde_json::Value),
+}
+
+impl Verdict {
+    /// Whether the contact is (or would be) recorded.
+    fn accepted(&self) -> bool {
+        matches!(self, Verdict::Accept(_))
+    }
+
+    /// The reply for this verdict.
+    fn to_json(&self) -> serde_json::Value {
+        match self {
+            Verdict::Accept(message) => serde_json::json!({
+                "status": "added",
+                "message": message
+            }),
+            Verdict::Refuse(reply) | Verdict::Suppressed(reply) | Verdict::SenderRefused(reply) => reply.clone(),
+        }
+    }
+}
+
+/// The lists a contact is checked against besides the store, locked for the
+/// duration of a request.
+struct Lists<'a> {
+    suppressions: &'a SuppressionList,
+    registry: &'a mut SenderRegistry,
+}
+
+/// The verdict for any contact with `key` while it is suppressed.
+fn suppressed_verdict(suppressions: &SuppressionList, key: &str) -> Option<Verdict> {
+    suppressions.get(key).map(|suppression| {
+        Verdict::Suppressed(serde_json::json!({
+            "status": "suppressed",
+            "message": "Number is on the suppression list",
+            "suppressed_at": suppression.suppressed_at,
+            "reason": suppression.reason
+        }))
+    })
+}
+
+/// In strict mode, the verdict for any `/addmulti` from `val` that the
+/// sender registry does not allow: an unregistered or disabled sender, or one
+/// that has reached its daily cap.
+fn sender_verdict(registry: &SenderRegistry, val: &str, config: &Config, now: DateTime<Utc>) -> Option<Verdict> {
+    if !config.strict_senders {
+        return None;
+    }
+    let refuse = |code: &str, message: String| {
+        Some(Verdict::SenderRefused(serde_json::json!({
+            "status": "error",
+            "code": code,
+            "message": message,
+            "sender": val
+        })))
+    };
+    let settings = match registry.get(val) {
+        Some(settings) => settings,
+        None => return refuse("unknown_sender", format!("Sender {} is not registered", val)),
+    };
+    if !settings.active {
+        return refuse("sender_disabled", format!("Sender {} is disabled", val));
+    }
+    match settings.daily_cap {
+        Some(cap) if registry.contacts_on(val, now.date_naive()) >= cap as usize => {
+            refuse("daily_cap_reached", format!("Sender {} has reached its daily cap of {}", val, cap))
+        }
+        _ => None,
+    }
+}
+
+/// Seeds the registry's count of the numbers `sender` last contacted on the
+/// UTC day of `now` from the store. This scans the sender's contacts, so it
+/// is only done at startup and when a sender is registered; accepted
+/// contacts then keep the count up to date.
+fn seed_contacts_today<B: StorageBackend>(
+    db: &B,
+    registry: &mut SenderRegistry,
+    sender: &str,
+    now: DateTime<Utc>,
+) -> std::io::Result<()> {
+    let midnight = now.date_naive().and_time(chrono::NaiveTime::MIN).and_utc();
+    let count = db.recipients_of(sender)?.iter().filter(|(_, r)| r.last_seen >= midnight).count();
+    registry.set_contacts(sender, now.date_naive(), count);
+    Ok(())
+}
+
+/// Decides an `/add` for a number currently holding `existing` senders.
+fn add_verdict(existing: Option<&[SenderRecord]>, config: &Config, now: DateTime<Utc>) -> Verdict {
+    // The number stays blocked until its latest contact has cooled down
+    let last_seen = existing.into_iter().flatten().map(|r| r.last_seen).max();
+    match last_seen.and_then(|t| cooldown_remaining(t, config.cooldown_days, now)) {
+        Some(remaining) => Verdict::Refuse(serde_json::json!({
+            "status": "exists",
+            "message": "Number already texted",
+            "cooldown_remaining_seconds": remaining_seconds(remaining)
+        })),
+        None if existing.is_some() => Verdict::Accept("Number re-added after cooldown"),
+        None => Verdict::Accept("New number added"),
+    }
+}
+
+/// Decides an `/addmulti` of sender `val` for a number currently holding
+/// `existing` senders.
+fn addmulti_verdict(
+    existing: Option<&[SenderRecord]>,
+    val: &str,
+    max_senders: usize,
+    config: &Config,
+    now: DateTime<Utc>,
+) -> Verdict {
+    let values = match existing {
+        Some(values) => values,
+        None => return Verdict::Accept("New key/sender combination added"),
+    };
+
+    let remaining = |r: &SenderRecord| cooldown_remaining(r.last_seen, config.cooldown_days, now);
+    let previous = values.iter().find(|r| r.sender == val);
+    if let Some(left) = previous.and_then(remaining) {
+        return Verdict::Refuse(serde_json::json!({
+            "status": "exists",
+            "message": "Number already texted from that sender",
+            "cooldown_remaining_seconds": remaining_seconds(left)
+        }));
+    }
+
+    // Only senders still within their cooldown count towards the limit
+    let active: Vec<TimeDelta> = values.iter().filter_map(remaining).collect();
+    if sender_limit_reached(active.len(), max_senders) {
+        Verdict::Refuse(serde_json::json!({
+            "status": "exists",
+            "message": "Number already texted. Max senders reached.",
+            "max_senders": max_senders,
+            "cooldown_remaining_seconds": active.into_iter().min().and_then(remaining_seconds)
+        }))
+    } else if previous.is_some() {
+        Verdict::Accept("Sender re-added after cooldown")
+    } else {
+        Verdict::Accept("New sender added to existing key")
+    }
+}
+
+/// Runs an `/add` of `val` to `key`, recording the contact if accepted. The
+/// caller persists.
+fn add_one<B: StorageBackend>(
+    db: &mut B,
+    lists: &mut Lists,
+    key: String,
+    val: String,
+    config: &Config,
+    now: DateTime<Utc>,
+) -> Result<Verdict, N2oError> {
+    if let Some(verdict) = suppressed_verdict(lists.suppressions, &key) {
+        return Ok(verdict);
+    }
+    let verdict = add_verdict(db.get(&key).map_err(N2oError::read)?.as_deref(), config, now);
+    if verdict.accepted() {
+        lists.registry.record_contact(&val, now.date_naive());
+        db.add_sender(&key, val, now);
+    }
+    Ok(verdict)
+}
+
+/// Runs an `/addmulti` of `val` to `key`, recording the contact if accepted.
+/// The caller persists.
+fn addmulti_one<B: StorageBackend>(
+    db: &mut B,
+    lists: &mut Lists,
+    key: String,
+    val: String,
+    max_senders: usize,
+    config: &Config,
+    now: DateTime<Utc>,
+) -> Result<Verdict, N2oError> {
+    if let Some(verdict) = suppressed_verdict(lists.suppressions, &key) {
+        return Ok(verdict);
+    }
+    if let Some(verdict) = sender_verdict(lists.registry, &val, config, now) {
+        return Ok(verdict);
+    }
+    let verdict = addmulti_verdict(db.get(&key).map_err(N2oError::read)?.as_deref(), &val, max_senders, config, now);
+    if verdict.accepted() {
+        lists.registry.record_contact(&val, now.date_naive());
+        db.add_sender(&key, val, now);
+    }
+    Ok(verdict)
+}
+
+/// Timestamps for the items of a batch or import: the current time, but
+/// always later than the previous item's. The journal takes a contact that is
+/// not newer than the record's `last_seen` for one it has already applied, so
+/// a contact repeated within a batch needs a time of its own to be replayed.
+#[derive(Default)]
+struct ItemClock {
+    last: Option<DateTime<Utc>>,
+}
+
+impl ItemClock {
+    fn now(&mut self) -> DateTime<Utc> {
+        let now = match self.last {
+            Some(last) => Utc::now().max(last + TimeDelta::nanoseconds(1)),
+            None => Utc::now(),
+        };
+        self.last = Some(now);
+        now
+    }
+}
+
+/// Runs a batch body (an array of `/add` or `/addmulti` objects) through
+/// `run_item` under one lock, then persists once if anything changed. Each
+/// item is given its own time by an `ItemClock`.
+///
+/// Items are applied in order, so a later item sees the effect of an earlier
+/// one for the same number. A failed read stops the batch; the items applied
+/// before it are still persisted.
+fn run_batch<B: StorageBackend>(
+    tenant: &Tenant<B>,
+    body: &serde_json::Value,
+    mut run_item: impl FnMut(&mut B, &mut Lists, &serde_json::Value, DateTime<Utc>) -> Result<Verdict, N2oError>,
+) -> Result<warp::reply::Json, N2oError> {
+    let items = body
+        .as_array()
+        .ok_or_else(|| N2oError::BadRequest("Expected a JSON array of {key, val} objects.".to_string()))?;
+
+    let suppressions = tenant.suppressions.lock().unwrap();
+    let mut registry = tenant.registry.lock().unwrap();
+    let mut lists = Lists { suppressions: &suppressions, registry: &mut registry };
+    let mut db = tenant.store.lock().unwrap();
+    let mut clock = ItemClock::default();
+    let mut changed = false;
+    let results: Result<Vec<serde_json::Value>, N2oError> = items
+        .iter()
+        .map(|item| {
+            let verdict = run_item(&mut *db, &mut lists, item, clock.now())?;
+            changed |= verdict.accepted();
+            Ok(verdict.to_json())
+        })
+        .collect();
+
+    if changed {
+        persist(&mut *db, &tenant.health).map_err(N2oError::Persist)?;
+    }
+    let results = results?;
+    Ok(warp::reply::json(&serde_json::json!({
+        "status": "ok",
+        "results": results
+    })))
+}
+
+/// Running totals of an `/import`, across the chunks of its body.
+#[derive(Default)]
+struct ImportSummary {
+    total: usize,
+    added: usize,
+    duplicate: usize,
+    suppressed: usize,
+    sender_refused: usize,
+    invalid: usize,
+    rejects: Vec<import::Rejected>,
+}
+
+/// Imports one chunk of `/import` rows with `/add` (or, with `multi`,
+/// `/addmulti`) semantics and persists them. The locks are held for the
+/// chunk only, so other requests are served between chunks; rows applied
+/// before a failed read are still persisted. `clock` dates each row, and
+/// carries over between chunks.
+fn import_rows<B: StorageBackend>(
+    tenant: &Tenant<B>,
+    rows: Vec<Result<import::ImportRow, import::Rejected>>,
+    multi: bool,
+    max_senders: usize,
+    clock: &mut ItemClock,
+    summary: &mut ImportSummary,
+) -> Result<(), N2oError> {
+    if rows.is_empty() {
+        return Ok(());
+    }
+    let config = &tenant.config;
+    let suppressions = tenant.suppressions.lock().unwrap();
+    let mut registry = tenant.registry.lock().unwrap();
+    let mut lists = Lists { suppressions: &suppressions, registry: &mut registry };
+    let mut db = tenant.store.lock().unwrap();
+    let mut added = 0;
+    let mut failed = None;
+
+    for row in rows {
+        summary.total += 1;
+        let row = match row {
+            Ok(row) => row,
+            Err(reject) => {
+                summary.invalid += 1;
+                summary.rejects.push(reject);
+                continue;
+            }
+        };
+
+        let (key, val) = match validation::phone_number("key", Role::Recipient, &row.key, config)
+            .and_then(|key| Ok((key, validation::phone_number("val", Role::Sender, &row.val, config)?)))
+        {
+            Ok(pair) => pair,
+            Err(e) => {
+                summary.invalid += 1;
+                summary.rejects.push(import::Rejected { line: row.line, key: row.key, val: row.val, reason: e.message() });
+                continue;
+            }
+        };
+
+        let verdict = if multi {
+            addmulti_one(&mut *db, &mut lists, key, val, max_senders, config, clock.now())
+        } else {
+            add_one(&mut *db, &mut lists, key, val, config, clock.now())
+        };
+        let verdict = match verdict {
+            Ok(verdict) => verdict,
+            Err(e) => {
+                failed = Some(e);
+                break;
+            }
+        };
+        let reply = match verdict {
+            Verdict::Accept(_) => {
+                added += 1;
+                continue;
+            }
+            Verdict::Refuse(reply) => {
+                summary.duplicate += 1;
+                reply
+            }
+            Verdict::Suppressed(reply) => {
+                summary.suppressed += 1;
+                reply
+            }
+            Verdict::SenderRefused(reply) => {
+                summary.sender_refused += 1;
+                reply
+            }
+        };
+        summary.rejects.push(import::Rejected {
+            line: row.line,
+            key: row.key,
+            val: row.val,
+            reason: reply["message"].as_str().unwrap_or_default().to_string(),
+        });
+    }
+
+    if added > 0 {
+        persist(&mut *db, &tenant.health).map_err(N2oError::Persist)?;
+        summary.added += added;
+    }
+    match failed {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+/// Streams an `/import` body through `RowParser`, applying the rows each
+/// chunk completes before reading the next.
+async fn run_import<B: StorageBackend>(
+    tenant: Tenant<B>,
+    query: HashMap<String, String>,
+    content_type: Option<String>,
+    body: impl futures_util::Stream<Item = Result<impl warp::hyper::body::Buf, warp::Error>>,
+) -> Result<warp::reply::Json, N2oError> {
+    let multi = match query.get("mode").map(String::as_str) {
+        None | Some("add") => false,
+        Some("addmulti") => true,
+        Some(other) => {
+            return Err(N2oError::BadRequest(format!(
+                "Unknown import mode '{}'; expected add or addmulti.",
+                other
+            )));
+        }
+    };
+    let max_senders = max_senders_query(&query, &tenant.config)?;
+    let format = import::ImportFormat::detect(query.get("format").map(String::as_str), content_type.as_deref())
+        .map_err(N2oError::BadRequest)?;
+    let key_column = query.get("key_column").map(String::as_str).unwrap_or(import::DEFAULT_KEY_COLUMN);
+    let val_column = query.get("val_column").map(String::as_str).unwrap_or(import::DEFAULT_VAL_COLUMN);
+    let with_rejects = query.get("rejects").is_some_and(|v| v == "true" || v == "1");
+
+    let mut clock = ItemClock::default();
+    let mut parser = import::RowParser::new(format, key_column, val_column);
+    let mut summary = ImportSummary::default();
+    let mut body = Box::pin(body);
+    while let Some(chunk) = body.next().await {
+        let mut chunk = chunk.map_err(|e| N2oError::BadRequest(format!("Could not read the request body: {}", e)))?;
+        let chunk = chunk.copy_to_bytes(chunk.remaining());
+        let rows = parser.feed(&chunk).map_err(N2oError::BadRequest)?;
+        import_rows(&tenant, rows, multi, max_senders, &mut clock, &mut summary)?;
+    }
+    let rows = parser.finish().map_err(N2oError::BadRequest)?;
+    import_rows(&tenant, rows, multi, max_senders, &mut clock, &mut summary)?;
+
+    log::info!(
+        "Imported {} rows: {} added, {} duplicate, {} suppressed, {} sender refused, {} invalid",
+        summary.total,
+        summary.added,
+        summary.duplicate,
+        summary.suppressed,
+        summary.sender_refused,
+        summary.invalid
+    );
+
+    Ok(warp::reply::json(&serde_json::json!({
+        "status": "ok",
+        "mode": if multi { "addmulti" } else { "add" },
+        "rows": summary.total,
+        "added": summary.added,
+        "duplicate": summary.duplicate,
+        "suppressed": summary.suppressed,
+        "sender_refused": summary.sender_refused,
+        "invalid": summary.invalid,
+        "rejects": with_rejects.then(|| import::rejects_csv(&summary.rejects))
+    })))
+}
+
+/// The `/check` report for one key (and optionally sender): what is stored,
+/// and what `/add` and `/addmulti` would do with it.
+fn check_report<B: StorageBackend>(
+    db: &B,
+    lists: &Lists,
+    key: &str,
+    val: Option<&str>,
+    max_senders: usize,
+    config: &Config,
+    now: DateTime<Utc>,
+) -> Result<serde_json::Value, N2oError> {
+    let existing = db.get(key).map_err(N2oError::read)?;
+    let suppressed = suppressed_verdict(lists.suppressions, key);
+    let add = suppressed.as_ref().map_or_else(|| add_verdict(existing.as_deref(), config, now).to_json(), Verdict::to_json);
+    let addmulti = val.map(|val| match &suppressed {
+        Some(verdict) => verdict.to_json(),
+        None => sender_verdict(lists.registry, val, config, now)
+            .unwrap_or_else(|| addmulti_verdict(existing.as_deref(), val, max_senders, config, now))
+            .to_json(),
+    });
+
+    Ok(serde_json::json!({
+        "key": key,
+        "val": val,
+        "exists": existing.is_some(),
+        "suppressed": suppressed.is_some(),
+        "add": add,
+        "addmulti": addmulti,
+        "senders": existing.unwrap_or_default()
+    }))
+}
+
+/// The reply to an invalid `max_senders`, in a body or query string.
+const MAX_SENDERS_INVALID: &str = "max_senders must be a non-negative integer (0 for unlimited).";
+
+/// Reads the optional per-request `max_senders` field of an `/addmulti` body.
+fn max_senders_override(body: &serde_json::Value) -> Result<Option<usize>, &'static str> {
+    match &body["max_senders"] {
+        serde_json::Value::Null => Ok(None),
+        value => value
+            .as_u64()
+            .map(|limit| Some(limit as usize))
+            .ok_or(MAX_SENDERS_INVALID),
+    }
+}
+
 /// Converts a single alphabetic character to its corresponding phone keypad digit.
 ///
-/// This is private because we only expose `convert_to_ten_digits` publicly.
-fn letter_to_digit(c: char) -> Option<char> {
+/// Crate-private: `convert_to_ten_digits` and `normalize` are the public ways in.
+pub(crate) fn letter_to_digit(c: char) -> Option<char> {
     match c.to_ascii_uppercase() {
         'A' | 'B' | 'C' => Some('2'),
         'D' | 'E' | 'F' => Some('3'),
@@ -95,233 +749,858 @@ pub fn convert_to_ten_digits(number: &str) -> String {
     }
 }
 
+/// Reads the optional `max_senders` query parameter, defaulting to the
+/// configured limit.
+fn max_senders_query(query: &HashMap<String, String>, config: &Config) -> Result<usize, N2oError> {
+    match query.get("max_senders") {
+        None => Ok(config.max_senders),
+        Some(v) => v.parse().map_err(|_| N2oError::BadRequest(MAX_SENDERS_INVALID.to_string())),
+    }
+}
+
+/// A registered sender as the `/senders` routes reply with it.
+fn registered_json(sender: &str, settings: &RegisteredSender) -> serde_json::Value {
+    let mut value = serde_json::json!(settings);
+    value["sender"] = sender.into();
+    value
+}
+
+/// Reads the `limit` query parameter of a paginated endpoint.
+fn page_size(query: &HashMap<String, String>) -> Result<usize, N2oError> {
+    match query.get("limit") {
+        None => Ok(DEFAULT_PAGE_SIZE),
+        Some(v) => v
+            .parse()
+            .ok()
+            .filter(|limit| (1..=MAX_PAGE_SIZE).contains(limit))
+            .ok_or_else(|| N2oError::BadRequest(format!("limit must be between 1 and {}.", MAX_PAGE_SIZE))),
+    }
+}
+
 /// Creates the combined Warp routes (filters) for our endpoints.
 ///
-/// Marked `pub` so integration tests in `tests/` can call it.
-pub fn create_routes(
-    store: Store,
-    valid_tokens: Vec<String>,
+/// `namespaces` holds the store of every namespace (a single `Store` is the
+/// `default` one); each gets its own settings from `config.for_namespace`.
+///
+/// Marked `pub` so integration tests in `tests/` can call it. Every failure
+/// is answered by `error::recover`, so the filter never rejects.
+pub fn create_routes<B: StorageBackend>(
+    namespaces: impl Into<Namespaces<B>>,
+    valid_tokens: Arc<TokenSet>,
     start_time: Instant,
-) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-    // This filter checks if the Authorization header is a valid token
-    let token_filter = warp::header::<String>("authorization")
-        .map(move |token: String| valid_tokens.contains(&token))
-        .boxed();
+    config: Config,
+) -> impl Filter<Extract = impl warp::Reply, Error = std::convert::Infallible> + Clone {
+    // Each namespace keeps its own store, suppression list, sender registry,
+    // settings and persistence health
+    let tenants: BTreeMap<String, Tenant<B>> = namespaces
+        .into()
+        .iter()
+        .map(|(name, store, suppressions, registry)| {
+            let tenant = Tenant {
+                name: name.to_string(),
+                store: Arc::clone(store),
+                suppressions: Arc::clone(suppressions),
+                registry: Arc::clone(registry),
+                health: Arc::new(Mutex::new(PersistHealth::default())),
+                config: Arc::new(config.for_namespace(name)),
+            };
+            // Daily caps start from what the store already holds for today
+            let mut registry = registry.lock().unwrap();
+            let db = store.lock().unwrap();
+            let senders: Vec<String> = registry.iter().map(|(sender, _)| sender.to_string()).collect();
+            for sender in senders {
+                if let Err(e) = seed_contacts_today(&*db, &mut registry, &sender, Utc::now()) {
+                    log::error!("Could not count today's contacts for sender {}: {}", sender, e);
+                }
+            }
+            (name.to_string(), tenant)
+        })
+        .collect();
+    let tenants = Arc::new(tenants);
 
-    // Reusable store filter
-    let store_filter = warp::any().map(move || Arc::clone(&store));
+    // Builds a filter that passes only requests whose Authorization header
+    // is a valid token with the given scope, and extracts the namespace
+    // the request is for. With `basic_auth`, the token may also be the
+    // password of HTTP Basic credentials.
+    let tokens = Arc::clone(&valid_tokens);
+    let tenant_tenants = Arc::clone(&tenants);
+    let auth_filter = move |scope: Scope, basic_auth: bool| {
+        let (tokens, tenants) = (Arc::clone(&tokens), Arc::clone(&tenant_tenants));
+        warp::path::full()
+            .and(warp::header::optional::<String>("authorization"))
+            .and_then(move |path: warp::path::FullPath, header: Option<String>| {
+                let token = match header {
+                    Some(header) if basic_auth => Some(auth::basic_password(&header).unwrap_or(header)),
+                    header => header,
+                };
+                let result = auth::authorize(&tokens.current(), token.as_deref(), scope)
+                    .and_then(|bound| resolve_tenant(&tenants, namespace::from_path(path.as_str()), bound.as_deref()));
+                std::future::ready(result.map_err(warp::reject::custom))
+            })
+            .boxed()
+    };
+    let tenant_filter = {
+        let auth_filter = auth_filter.clone();
+        move |scope: Scope| auth_filter(scope, false)
+    };
 
     // /add endpoint
 	let add_route = warp::path("add")
+		.and(warp::path::end())
 		.and(warp::post())
-		.and(token_filter.clone())
-		.and(store_filter.clone())
+		.and(tenant_filter(Scope::Add))
 		.and(warp::body::json())
-		.map(|is_valid: bool, store: Store, body: serde_json::Value| {
-			if !is_valid {
-				return warp::reply::json(&serde_json::json!({
-					"status": "error",
-					"message": "Invalid token"
-				}));
-			}
-
-			// Convert both key and val to 10-digit numbers
-			let key = convert_to_ten_digits(body["key"].as_str().unwrap_or(""));
-			let raw_val = body["val"].as_str().unwrap_or("");
-			let val = convert_to_ten_digits(raw_val);
+		.map(|Tenant { store, suppressions, registry, health, config, .. }: Tenant<B>, body: serde_json::Value| {
+			// Convert both key and val to 10-digit numbers, which must be valid NANP numbers
+			let (key, val) = validation::key_and_val(&body, &config)?;
 
+			let suppressions = suppressions.lock().unwrap();
+			let mut registry = registry.lock().unwrap();
+			let mut lists = Lists { suppressions: &suppressions, registry: &mut registry };
 			let mut db = store.lock().unwrap();
-			if db.contains_key(&key) {
-				warp::reply::json(&serde_json::json!({
-					"status": "exists",
-					"message": "Number already texted"
-				}))
-			} else {
-				db.insert(key, vec![val]);
-				// Persist
-				save_data(DATA_FILE, &db);
-				warp::reply::json(&serde_json::json!({
-					"status": "added",
-					"message": "New number added"
-				}))
-			}
-		});
+			let verdict = add_one(&mut *db, &mut lists, key, val, &config, Utc::now())?;
+			verdict_reply(&mut *db, &health, verdict)
+		})
+		.and_then(error::reject);
 
 
     // /addmulti endpoint
 	let addmulti_route = warp::path("addmulti")
+		.and(warp::path::end())
 		.and(warp::post())
-		.and(token_filter.clone())
-		.and(store_filter.clone())
+		.and(tenant_filter(Scope::Add))
 		.and(warp::body::json())
-		.map(|is_valid: bool, store: Store, body: serde_json::Value| {
-			if !is_valid {
-				return warp::reply::json(&serde_json::json!({
-					"status": "error",
-					"message": "Invalid token"
-				}));
-			}
-
-			// Convert both key and val to 10-digit numbers
-			let key = convert_to_ten_digits(body["key"].as_str().unwrap_or(""));
-			let raw_val = body["val"].as_str().unwrap_or("");
-			let val = convert_to_ten_digits(raw_val);
+		.map(|Tenant { store, suppressions, registry, health, config, .. }: Tenant<B>, body: serde_json::Value| {
+			// Convert both key and val to 10-digit numbers, which must be valid NANP numbers
+			let (key, val) = validation::key_and_val(&body, &config)?;
 
+			// The request may override the configured sender limit
+			let max_senders = max_senders_override(&body)
+				.map_err(|message| N2oError::BadRequest(message.to_string()))?
+				.unwrap_or(config.max_senders);
+
+			let suppressions = suppressions.lock().unwrap();
+			let mut registry = registry.lock().unwrap();
+			let mut lists = Lists { suppressions: &suppressions, registry: &mut registry };
 			let mut db = store.lock().unwrap();
-			match db.get_mut(&key) {
-				Some(values) => {
-					if values.contains(&val) {
-						warp::reply::json(&serde_json::json!({
-							"status": "exists",
-							"message": "Number already texted from that sender"
-						}))
-					} else {
-						// Key exists, but add a new sender if < 2
-						if values.len() < 2 {
-							values.push(val);
-							save_data(DATA_FILE, &db);
-							warp::reply::json(&serde_json::json!({
-								"status": "added",
-								"message": "New sender added to existing key"
-							}))
-						} else {
-							warp::reply::json(&serde_json::json!({
-								"status": "exists",
-								"message": "Number already texted. Max senders reached."
-							}))
-						}
-					}
-				}
-				None => {
-					// Key doesn't exist yet
-					db.insert(key, vec![val]);
-					save_data(DATA_FILE, &db);
-					warp::reply::json(&serde_json::json!({
-						"status": "added",
-						"message": "New key/sender combination added"
-					}))
-				}
-			}
-		});
+			let verdict = addmulti_one(&mut *db, &mut lists, key, val, max_senders, &config, Utc::now())?;
+			verdict_reply(&mut *db, &health, verdict)
+		})
+		.and_then(error::reject);
+
 
+    // /add/batch and /addmulti/batch endpoints: arrays of the single-item
+    // bodies, with one result per item
+    let add_batch_route = warp::path!("add" / "batch")
+        .and(warp::post())
+        .and(tenant_filter(Scope::Add))
+        .and(warp::body::json())
+        .map(|tenant: Tenant<B>, body: serde_json::Value| {
+            let config = &tenant.config;
+            run_batch(&tenant, &body, |db, lists, item, now| match validation::key_and_val(item, config) {
+                Ok((key, val)) => add_one(db, lists, key, val, config, now),
+                Err(e) => Ok(Verdict::Refuse(e.to_json())),
+            })
+        })
+        .and_then(error::reject);
+
+    let addmulti_batch_route = warp::path!("addmulti" / "batch")
+        .and(warp::post())
+        .and(tenant_filter(Scope::Add))
+        .and(warp::body::json())
+        .map(|tenant: Tenant<B>, body: serde_json::Value| {
+            let config = &tenant.config;
+            run_batch(&tenant, &body, |db, lists, item, now| {
+                let max_senders = match max_senders_override(item) {
+                    Ok(limit) => limit.unwrap_or(config.max_senders),
+                    Err(message) => {
+                        return Ok(Verdict::Refuse(serde_json::json!({
+                            "status": "error",
+                            "message": message
+                        })));
+                    }
+                };
+                match validation::key_and_val(item, config) {
+                    Ok((key, val)) => addmulti_one(db, lists, key, val, max_senders, config, now),
+                    Err(e) => Ok(Verdict::Refuse(e.to_json())),
+                }
+            })
+        })
+        .and_then(error::reject);
+
+    // /import endpoint: a CSV or NDJSON body of key/val rows
+    let import_route = warp::path("import")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(tenant_filter(Scope::Add))
+        .and(warp::query::<HashMap<String, String>>())
+        .and(warp::header::optional::<String>("content-type"))
+        .and(warp::body::content_length_limit(IMPORT_MAX_BYTES))
+        .and(warp::body::stream())
+        .then(run_import)
+        .and_then(error::reject);
+
+    // /check endpoint: GET for one number, POST with an array for many.
+    // Nothing is written.
+    let check_route = warp::path("check")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(tenant_filter(Scope::Check))
+        .and(warp::query::<HashMap<String, String>>())
+        .map(|Tenant { store, suppressions, registry, config, .. }: Tenant<B>, query: HashMap<String, String>| {
+            let max_senders = max_senders_query(&query, &config)?;
+            let (key, val) = validation::key_and_optional_val(&serde_json::json!(query), &config)?;
+
+            let suppressions = suppressions.lock().unwrap();
+            let mut registry = registry.lock().unwrap();
+            let lists = Lists { suppressions: &suppressions, registry: &mut registry };
+            let db = store.lock().unwrap();
+            let mut report = check_report(&*db, &lists, &key, val.as_deref(), max_senders, &config, Utc::now())?;
+            report["status"] = "ok".into();
+            Ok(warp::reply::json(&report))
+        })
+        .and_then(error::reject);
+
+    let check_batch_route = warp::path("check")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(tenant_filter(Scope::Check))
+        .and(warp::body::json())
+        .map(|Tenant { store, suppressions, registry, config, .. }: Tenant<B>, body: serde_json::Value| {
+            let items = body
+                .as_array()
+                .ok_or_else(|| N2oError::BadRequest("Expected a JSON array of {key, val} objects.".to_string()))?;
+
+            let now = Utc::now();
+            let suppressions = suppressions.lock().unwrap();
+            let mut registry = registry.lock().unwrap();
+            let lists = Lists { suppressions: &suppressions, registry: &mut registry };
+            let db = store.lock().unwrap();
+            let results = items
+                .iter()
+                .map(|item| {
+                    let max_senders = match max_senders_override(item) {
+                        Ok(limit) => limit.unwrap_or(config.max_senders),
+                        Err(message) => {
+                            return Ok(serde_json::json!({
+                                "status": "error",
+                                "message": message
+                            }));
+                        }
+                    };
+                    match validation::key_and_optional_val(item, &config) {
+                        Ok((key, val)) => check_report(&*db, &lists, &key, val.as_deref(), max_senders, &config, now),
+                        Err(e) => Ok(e.to_json()),
+                    }
+                })
+                .collect::<Result<Vec<_>, N2oError>>()?;
+
+            Ok(warp::reply::json(&serde_json::json!({
+                "status": "ok",
+                "results": results
+            })))
+        })
+        .and_then(error::reject);
 
     // /dump endpoint
     let dump_route = warp::path("dump")
         .and(warp::get())
-        .and(token_filter.clone())
-        .and(store_filter.clone())
-        .map(|is_valid: bool, store: Store| -> Box<dyn warp::Reply> {
-            if !is_valid {
-                return Box::new(warp::reply::json(&serde_json::json!({
-                    "status": "error",
-                    "message": "Invalid token"
-                })));
-            }
-
+        .and(tenant_filter(Scope::Dump))
+        .map(|Tenant { store, .. }: Tenant<B>| -> Result<_, N2oError> {
             let db = store.lock().unwrap();
-            let mut csv_data = String::from("phone_number,senders\n");
-            for (k, v) in db.iter() {
-                let senders = v.join("|");
-                csv_data.push_str(&format!("{},{}\n", k, senders));
+            // One row per number; the per-sender columns are `|`-separated
+            // in the same order as `senders`
+            let mut csv_data = String::from("phone_number,senders,first_seen,last_seen,contact_count\n");
+            for (k, v) in db.iter().map_err(N2oError::read)? {
+                let column = |f: &dyn Fn(&SenderRecord) -> String| v.iter().map(f).collect::<Vec<_>>().join("|");
+                csv_data.push_str(&format!(
+                    "{},{},{},{},{}\n",
+                    k,
+                    column(&|r| r.sender.clone()),
+                    column(&|r| r.first_seen.to_rfc3339_opts(SecondsFormat::Secs, true)),
+                    column(&|r| r.last_seen.to_rfc3339_opts(SecondsFormat::Secs, true)),
+                    column(&|r| r.count.to_string()),
+                ));
             }
 
-            let response = warp::http::Response::builder()
+            Ok(warp::http::Response::builder()
                 .header("Content-Type", "text/csv")
                 .body(csv_data)
-                .unwrap();
-
-            Box::new(response)
-        });
+                .unwrap())
+        })
+        .and_then(error::reject);
 
     // /clear endpoint
     let clear_route = warp::path("clear")
         .and(warp::post())
-        .and(token_filter.clone())
-        .and(store_filter.clone())
-        .map(|is_valid: bool, store: Store| {
-            if !is_valid {
-                return warp::reply::json(&serde_json::json!({
-                    "status": "error",
-                    "message": "Invalid token"
-                }));
-            }
+        .and(tenant_filter(Scope::Clear))
+        .map(|Tenant { store, health, config, .. }: Tenant<B>| {
+            let mut db = store.lock().unwrap();
 
             // Attempt to archive data first
-            match archive_data(DATA_FILE) {
-                Ok(_) => println!("Data archived successfully."),
+            match archive_data(&*db, &config) {
+                Ok(_) => log::info!("Data archived successfully."),
                 Err(e) => {
-                    eprintln!("Failed to archive data: {}", e);
-                    return warp::reply::json(&serde_json::json!({
-                        "status": "error",
-                        "message": "Failed to archive data before clearing."
-                    }));
+                    log::error!("Failed to archive data: {}", e);
+                    return Err(N2oError::Archive(e.to_string()));
                 }
             }
 
-            let mut db = store.lock().unwrap();
             db.clear();
-            save_data(DATA_FILE, &db);
-            warp::reply::json(&serde_json::json!({
+            persist(&mut *db, &health).map_err(N2oError::Persist)?;
+            Ok(warp::reply::json(&serde_json::json!({
                 "status": "cleared",
                 "message": "All data cleared and archived."
+            })))
+        })
+        .and_then(error::reject);
+
+    // DELETE /number/{key} endpoint: removes one number and all its senders,
+    // recording them in the audit trail first
+    let delete_number_route = warp::path!("number" / String)
+        .and(warp::delete())
+        .and(tenant_filter(Scope::Clear))
+        .and(warp::header::<String>("authorization"))
+        .map(|raw_key: String, Tenant { store, health, config, .. }: Tenant<B>, token: String| {
+            let key = validation::path_number("key", Role::Recipient, &raw_key, &config)?;
+
+            let mut db = store.lock().unwrap();
+            let removed = db.get(&key).map_err(N2oError::read)?.ok_or_else(|| N2oError::NumberNotFound(key.clone()))?;
+            let entry = audit::AuditEntry {
+                at: Utc::now(),
+                token: auth::fingerprint(&token),
+                action: audit::AuditAction::DeleteNumber { key: key.clone(), removed: removed.clone() },
+            };
+            audit::record(&config.audit_file, &entry).map_err(|e| N2oError::Audit(e.to_string()))?;
+
+            db.remove(&key);
+            persist(&mut *db, &health).map_err(N2oError::Persist)?;
+            log::info!("Deleted {} ({} senders)", key, removed.len());
+            Ok(warp::reply::json(&serde_json::json!({
+                "status": "deleted",
+                "key": key,
+                "removed": removed
+            })))
+        })
+        .and_then(error::reject);
+
+    // DELETE /number/{key}/sender/{val} endpoint: removes one sender, and the
+    // number with it if that was its last sender
+    let delete_sender_route = warp::path!("number" / String / "sender" / String)
+        .and(warp::delete())
+        .and(tenant_filter(Scope::Clear))
+        .and(warp::header::<String>("authorization"))
+        .map(|raw_key: String, raw_val: String, Tenant { store, health, config, .. }: Tenant<B>, token: String| {
+            let key = validation::path_number("key", Role::Recipient, &raw_key, &config)?;
+            let val = validation::path_number("val", Role::Sender, &raw_val, &config)?;
+
+            let mut db = store.lock().unwrap();
+            let mut senders = db.get(&key).map_err(N2oError::read)?.ok_or_else(|| N2oError::NumberNotFound(key.clone()))?;
+            let position = senders
+                .iter()
+                .position(|r| r.sender == val)
+                .ok_or_else(|| N2oError::SenderNotFound { key: key.clone(), sender: val.clone() })?;
+            let removed = senders.remove(position);
+            let entry = audit::AuditEntry {
+                at: Utc::now(),
+                token: auth::fingerprint(&token),
+                action: audit::AuditAction::DeleteSender { key: key.clone(), removed: removed.clone() },
+            };
+            audit::record(&config.audit_file, &entry).map_err(|e| N2oError::Audit(e.to_string()))?;
+
+            let remaining = senders.len();
+            if senders.is_empty() {
+                db.remove(&key);
+            } else {
+                db.insert(key.clone(), senders);
+            }
+            persist(&mut *db, &health).map_err(N2oError::Persist)?;
+            log::info!("Deleted sender {} of {}", val, key);
+            Ok(warp::reply::json(&serde_json::json!({
+                "status": "deleted",
+                "key": key,
+                "removed": removed,
+                "remaining_senders": remaining
+            })))
+        })
+        .and_then(error::reject);
+
+    // /sender/{val}/numbers endpoint: the numbers a sender contacted, by key,
+    // a page at a time. `after` is the last key of the previous page.
+    let sender_numbers_route = warp::path!("sender" / String / "numbers")
+        .and(warp::get())
+        .and(tenant_filter(Scope::Check))
+        .and(warp::query::<HashMap<String, String>>())
+        .map(|raw_val: String, Tenant { store, config, .. }: Tenant<B>, query: HashMap<String, String>| {
+            let val = validation::path_number("val", Role::Sender, &raw_val, &config)?;
+            let limit = page_size(&query)?;
+            let after = query.get("after");
+
+            let recipients = store.lock().unwrap().recipients_of(&val).map_err(N2oError::read)?;
+            let total = recipients.len();
+            let mut page: Vec<serde_json::Value> = recipients
+                .into_iter()
+                .filter(|(key, _)| after.is_none_or(|after| key > after))
+                .take(limit + 1)
+                .map(|(key, record)| {
+                    serde_json::json!({
+                        "key": key,
+                        "first_seen": record.first_seen,
+                        "last_seen": record.last_seen,
+                        "count": record.count
+                    })
+                })
+                .collect();
+            // One extra item tells whether there is another page
+            let next = (page.len() > limit).then(|| {
+                page.truncate(limit);
+                page[limit - 1]["key"].clone()
+            });
+            Ok(warp::reply::json(&serde_json::json!({
+                "status": "ok",
+                "sender": val,
+                "total": total,
+                "numbers": page,
+                "next": next
+            })))
+        })
+        .and_then(error::reject);
+
+    // /sender/{val}/stats endpoint
+    let sender_stats_route = warp::path!("sender" / String / "stats")
+        .and(warp::get())
+        .and(tenant_filter(Scope::Check))
+        .map(|raw_val: String, Tenant { store, config, .. }: Tenant<B>| {
+            let val = validation::path_number("val", Role::Sender, &raw_val, &config)?;
+
+            let recipients = store.lock().unwrap().recipients_of(&val).map_err(N2oError::read)?;
+            let records = || recipients.iter().map(|(_, record)| record);
+            Ok(warp::reply::json(&serde_json::json!({
+                "status": "ok",
+                "sender": val,
+                "numbers": recipients.len(),
+                "contacts": records().map(|r| r.count).sum::<u64>(),
+                "first_seen": records().map(|r| r.first_seen).min(),
+                "last_seen": records().map(|r| r.last_seen).max()
+            })))
+        })
+        .and_then(error::reject);
+
+    // /senders endpoint: every registered sender
+    let senders_route = warp::path("senders")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(tenant_filter(Scope::Check))
+        .map(|Tenant { registry, config, .. }: Tenant<B>| {
+            let registry = registry.lock().unwrap();
+            let senders: Vec<serde_json::Value> =
+                registry.iter().map(|(sender, settings)| registered_json(sender, settings)).collect();
+            warp::reply::json(&serde_json::json!({
+                "status": "ok",
+                "strict": config.strict_senders,
+                "senders": senders
             }))
         });
 
-    // /status endpoint
+    // PUT /senders/{val} endpoint: registers a sender, or replaces the
+    // settings of a registered one
+    let register_sender_route = warp::path!("senders" / String)
+        .and(warp::put())
+        .and(tenant_filter(Scope::Senders))
+        .and(warp::body::json())
+        .map(|raw_val: String, Tenant { store, registry, health, config, .. }: Tenant<B>, settings: RegisteredSender| {
+            let val = validation::path_number("val", Role::Sender, &raw_val, &config)?;
+            if settings.daily_cap == Some(0) {
+                return Err(N2oError::BadRequest("daily_cap must be at least 1; leave it out for no cap.".to_string()));
+            }
+
+            let mut registry = registry.lock().unwrap();
+            let previous = registry.register(val.clone(), settings.clone());
+            if previous.is_none() {
+                let db = store.lock().unwrap();
+                seed_contacts_today(&*db, &mut registry, &val, Utc::now()).map_err(N2oError::read)?;
+            }
+            record_flush(PersistTarget::Registry, registry.flush(), &health).map_err(N2oError::Persist)?;
+            let mut reply = registered_json(&val, &settings);
+            reply["status"] = if previous.is_some() { "updated" } else { "registered" }.into();
+            Ok(warp::reply::json(&reply))
+        })
+        .and_then(error::reject);
+
+    // POST /senders/{val}/enable and /senders/{val}/disable endpoints
+    let sender_active_route = warp::path!("senders" / String / String)
+        .and(warp::post())
+        .and(tenant_filter(Scope::Senders))
+        .map(|raw_val: String, action: String, Tenant { registry, health, config, .. }: Tenant<B>| {
+            let active = match action.as_str() {
+                "enable" => true,
+                "disable" => false,
+                other => {
+                    return Err(N2oError::BadRequest(format!(
+                        "Unknown action '{}'; expected enable or disable.",
+                        other
+                    )));
+                }
+            };
+            let val = validation::path_number("val", Role::Sender, &raw_val, &config)?;
+
+            let mut registry = registry.lock().unwrap();
+            let mut reply = match registry.set_active(&val, active) {
+                Some(settings) => registered_json(&val, settings),
+                None => return Err(N2oError::UnknownSender(val)),
+            };
+            record_flush(PersistTarget::Registry, registry.flush(), &health).map_err(N2oError::Persist)?;
+            log::info!("Sender {} {}d", val, action);
+            reply["status"] = "ok".into();
+            Ok(warp::reply::json(&reply))
+        })
+        .and_then(error::reject);
+
+    // DELETE /senders/{val} endpoint: unregisters a sender. Its contacts stay
+    // in the store.
+    let delete_registered_route = warp::path!("senders" / String)
+        .and(warp::delete())
+        .and(tenant_filter(Scope::Senders))
+        .map(|raw_val: String, Tenant { registry, health, config, .. }: Tenant<B>| {
+            let val = validation::path_number("val", Role::Sender, &raw_val, &config)?;
+
+            let mut registry = registry.lock().unwrap();
+            let removed = registry.remove(&val).ok_or_else(|| N2oError::UnknownSender(val.clone()))?;
+            record_flush(PersistTarget::Registry, registry.flush(), &health).map_err(N2oError::Persist)?;
+            let mut reply = registered_json(&val, &removed);
+            reply["status"] = "deleted".into();
+            Ok(warp::reply::json(&reply))
+        })
+        .and_then(error::reject);
+
+    // /suppress endpoint: adds a number to the suppression list, after which
+    // no sender may contact it. Suppressing it again keeps the original entry.
+    let suppress_route = warp::path("suppress")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(tenant_filter(Scope::Suppress))
+        .and(warp::body::json())
+        .map(|Tenant { suppressions, health, config, .. }: Tenant<B>, body: serde_json::Value| {
+            let key = validation::phone_field(&body, "key", Role::Recipient, &config)?;
+            let reason = match &body["reason"] {
+                serde_json::Value::Null => None,
+                serde_json::Value::String(reason) => Some(reason.clone()),
+                _ => return Err(N2oError::BadRequest("reason must be a string.".to_string())),
+            };
+
+            let mut suppressions = suppressions.lock().unwrap();
+            let added = suppressions.suppress(key.clone(), reason, SuppressionSource::Api, Utc::now());
+            // Flushed even if the number already was suppressed, in case the
+            // flush that added it failed
+            record_flush(PersistTarget::Suppressions, suppressions.flush(), &health).map_err(N2oError::Persist)?;
+            Ok(warp::reply::json(&serde_json::json!({
+                "status": "suppressed",
+                "key": key,
+                "message": if added { "Number suppressed" } else { "Number already suppressed" },
+                "suppressed_at": suppressions.get(&key).map(|s| s.suppressed_at)
+            })))
+        })
+        .and_then(error::reject);
+
+    // /unsuppress endpoint
+    let unsuppress_route = warp::path("unsuppress")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(tenant_filter(Scope::Suppress))
+        .and(warp::body::json())
+        .map(|Tenant { suppressions, health, config, .. }: Tenant<B>, body: serde_json::Value| {
+            let key = validation::phone_field(&body, "key", Role::Recipient, &config)?;
+
+            let mut suppressions = suppressions.lock().unwrap();
+            let removed = suppressions.unsuppress(&key);
+            record_flush(PersistTarget::Suppressions, suppressions.flush(), &health).map_err(N2oError::Persist)?;
+            if removed.is_none() {
+                return Err(N2oError::NotSuppressed(key));
+            }
+            Ok(warp::reply::json(&serde_json::json!({
+                "status": "unsuppressed",
+                "key": key,
+                "message": "Number removed from the suppression list"
+            })))
+        })
+        .and_then(error::reject);
+
+    // /suppress/import endpoint: a CSV or NDJSON body of numbers, with an
+    // optional reason each
+    let suppress_import_route = warp::path!("suppress" / "import")
+        .and(warp::post())
+        .and(tenant_filter(Scope::Suppress))
+        .and(warp::query::<HashMap<String, String>>())
+        .and(warp::header::optional::<String>("content-type"))
+        .and(warp::body::content_length_limit(IMPORT_MAX_BYTES))
+        .and(warp::body::bytes())
+        .map(|Tenant { suppressions, health, config, .. }: Tenant<B>,
+              query: HashMap<String, String>,
+              content_type: Option<String>,
+              body: warp::hyper::body::Bytes| {
+            let format = import::ImportFormat::detect(query.get("format").map(String::as_str), content_type.as_deref())
+                .map_err(N2oError::BadRequest)?;
+            let key_column = query.get("key_column").map(String::as_str).unwrap_or(import::DEFAULT_KEY_COLUMN);
+            let reason_column = query.get("reason_column").map(String::as_str).unwrap_or(import::DEFAULT_REASON_COLUMN);
+            let with_rejects = query.get("rejects").is_some_and(|v| v == "true" || v == "1");
+            let rows = import::suppression_rows(&body, format, key_column, reason_column).map_err(N2oError::BadRequest)?;
+
+            let now = Utc::now();
+            let mut suppressions = suppressions.lock().unwrap();
+            let (mut total, mut added, mut already, mut invalid) = (0, 0, 0, 0);
+            let mut rejects = Vec::new();
+            for row in rows {
+                total += 1;
+                let row = match row {
+                    Ok(row) => row,
+                    Err(reject) => {
+                        invalid += 1;
+                        rejects.push(reject);
+                        continue;
+                    }
+                };
+                match validation::phone_number("key", Role::Recipient, &row.key, &config) {
+                    Ok(key) => {
+                        let reason = (!row.val.is_empty()).then(|| row.val.clone());
+                        if suppressions.suppress(key, reason, SuppressionSource::Api, now) {
+                            added += 1;
+                        } else {
+                            already += 1;
+                        }
+                    }
+                    Err(e) => {
+                        invalid += 1;
+                        rejects.push(import::Rejected { line: row.line, key: row.key, val: row.val, reason: e.message() });
+                    }
+                }
+            }
+
+            record_flush(PersistTarget::Suppressions, suppressions.flush(), &health).map_err(N2oError::Persist)?;
+            log::info!("Suppressed {} of {} imported numbers ({} already, {} invalid)", added, total, already, invalid);
+
+            Ok(warp::reply::json(&serde_json::json!({
+                "status": "ok",
+                "rows": total,
+                "suppressed": added,
+                "already_suppressed": already,
+                "invalid": invalid,
+                "rejects": with_rejects.then(|| import::rejects_csv(&rejects))
+            })))
+        })
+        .and_then(error::reject);
+
+    // /inbound endpoint: a webhook for inbound SMS. Opt-out and opt-in
+    // keywords update the suppression list; other messages are ignored.
+    // Providers that cannot set the header send the token as the password of
+    // the webhook URL instead.
+    let inbound_route = warp::path("inbound")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(auth_filter(Scope::Suppress, true))
+        .and(warp::header::optional::<String>("content-type"))
+        .and(warp::body::content_length_limit(INBOUND_MAX_BYTES))
+        .and(warp::body::bytes())
+        .map(|Tenant { suppressions, health, config, .. }: Tenant<B>,
+              content_type: Option<String>,
+              body: warp::hyper::body::Bytes| {
+            let message = inbound::InboundMessage::parse(&body, content_type.as_deref()).map_err(N2oError::BadRequest)?;
+            let from = validation::phone_number("from", Role::Recipient, &message.from, &config)?;
+            let action = config.inbound.action(&message.text);
+
+            let mut suppressions = suppressions.lock().unwrap();
+            let mut not_lifted = false;
+            let changed = match &action {
+                Some((inbound::Action::OptOut, keyword)) => suppressions.suppress(
+                    from.clone(),
+                    Some(format!("Inbound {}", keyword)),
+                    SuppressionSource::Inbound,
+                    Utc::now(),
+                ),
+                // An opt-in only undoes an opt-out; a suppression added
+                // through the API stays until it is lifted there
+                Some((inbound::Action::OptIn, _)) => match suppressions.get(&from).map(|s| s.source) {
+                    Some(SuppressionSource::Inbound) => suppressions.unsuppress(&from).is_some(),
+                    Some(SuppressionSource::Api) => {
+                        not_lifted = true;
+                        log::info!("Inbound '{}' from {} did not lift a suppression added through the API", message.text.trim(), from);
+                        false
+                    }
+                    None => false,
+                },
+                None => false,
+            };
+            record_flush(PersistTarget::Suppressions, suppressions.flush(), &health).map_err(N2oError::Persist)?;
+            if changed {
+                log::info!("Inbound '{}' from {} updated the suppression list", message.text.trim(), from);
+            }
+            let (action, keyword) = action.unzip();
+            Ok(warp::reply::json(&serde_json::json!({
+                "status": "ok",
+                "from": from,
+                "action": action,
+                "keyword": keyword,
+                "changed": changed,
+                "not_lifted": not_lifted
+            })))
+        })
+        .and_then(error::reject);
+
+    // /normalize endpoint: shows how a number would be normalized, step by
+    // step, without storing anything
+    let normalize_route = warp::path("normalize")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(tenant_filter(Scope::Check))
+        .and(warp::query::<HashMap<String, String>>())
+        .map(|Tenant { config, .. }: Tenant<B>, query: HashMap<String, String>| {
+            let number = query.get("number").ok_or_else(|| ValidationError {
+                field: "number".to_string(),
+                code: validation::ErrorCode::MissingField,
+                normalized: None,
+            })?;
+            let role = match query.get("role").map(String::as_str) {
+                None | Some("recipient") => Role::Recipient,
+                Some("sender") => Role::Sender,
+                Some(other) => {
+                    return Err(N2oError::BadRequest(format!(
+                        "Unknown role '{}'; expected recipient or sender.",
+                        other
+                    )));
+                }
+            };
+
+            let normalization = normalize::normalize(number, role, &config);
+            let (normalized, error) = match &normalization.result {
+                Ok(number) => (Some(number.clone()), None),
+                Err((code, partial)) => (partial.clone(), Some(*code)),
+            };
+            Ok(warp::reply::json(&serde_json::json!({
+                "status": "ok",
+                "input": number,
+                "role": role,
+                "number_format": config.number_format,
+                "policy": config.normalization,
+                "steps": normalization.steps,
+                "normalized": normalized,
+                "error": error
+            })))
+        })
+        .and_then(error::reject);
+
+    // /status endpoint: counts for the request's namespace, and for every
+    // namespace when the token is not bound to one
     let status_route = warp::path("status")
         .and(warp::get())
-        .and(token_filter.clone())
-        .and(store_filter.clone())
-        .map(move |is_valid: bool, store: Store| {
-            if !is_valid {
-                return warp::reply::json(&serde_json::json!({
-                    "status": "error",
-                    "message": "Invalid token"
-                }));
-            }
-            let db = store.lock().unwrap();
-            let total_keys = db.len();
-            let total_values: usize = db.values().map(|vals| vals.len()).sum();
+        .and(tenant_filter(Scope::Check))
+        .and(warp::header::optional::<String>("authorization"))
+        .map(move |Tenant { name, store, suppressions, registry, health, config }: Tenant<B>, token: Option<String>| -> Result<_, N2oError> {
+            let counts = |store: &Store<B>| -> Result<_, N2oError> {
+                let db = store.lock().unwrap();
+                let values = db.iter().map_err(N2oError::read)?.map(|(_, vals)| vals.len()).sum::<usize>();
+                Ok((db.len().map_err(N2oError::read)?, values))
+            };
+            let (total_keys, total_values) = counts(&store)?;
             let uptime = Instant::now().duration_since(start_time);
+            // Copied out so the guard is not held while tenant locks are
+            // taken: writers lock the tenant first and health last
+            let persistence = {
+                let health = health.lock().unwrap();
+                serde_json::json!({
+                    "status": if health.failing.is_empty() { "ok" } else { "error" },
+                    "failing": health.failing,
+                    "last_error": health.last_error,
+                    "last_error_at": health.last_error_at
+                })
+            };
 
-            warp::reply::json(&serde_json::json!({
+            let bound = auth::authorize(&valid_tokens.current(), token.as_deref(), Scope::Check).ok().flatten();
+            let namespaces = bound.is_none().then(|| {
+                tenants
+                    .iter()
+                    .map(|(name, tenant)| {
+                        let (keys, values) = counts(&tenant.store)?;
+                        let suppressed = tenant.suppressions.lock().unwrap().len();
+                        let senders = tenant.registry.lock().unwrap().len();
+                        let summary = serde_json::json!({
+                            "keys": keys,
+                            "values": values,
+                            "suppressed": suppressed,
+                            "senders": senders
+                        });
+                        Ok((name.clone(), summary))
+                    })
+                    .collect::<Result<serde_json::Map<_, _>, N2oError>>()
+            });
+            let namespaces = namespaces.transpose()?;
+
+            // What each registered sender has done, and today against its cap
+            let senders = {
+                let now = Utc::now();
+                let registry = registry.lock().unwrap();
+                let db = store.lock().unwrap();
+                registry
+                    .iter()
+                    .map(|(sender, settings)| {
+                        let recipients = db.recipients_of(sender).map_err(N2oError::read)?;
+                        let mut summary = serde_json::json!(settings);
+                        summary["numbers"] = recipients.len().into();
+                        summary["contacts"] = recipients.iter().map(|(_, r)| r.count).sum::<u64>().into();
+                        summary["numbers_today"] = registry.contacts_on(sender, now.date_naive()).into();
+                        Ok((sender.to_string(), summary))
+                    })
+                    .collect::<Result<serde_json::Map<_, _>, N2oError>>()?
+            };
+
+            let mut reply = serde_json::json!({
                 "status": "ok",
+                "namespace": name,
                 "keys": total_keys,
                 "values": total_values,
-                "uptime_seconds": uptime.as_secs()
-            }))
-        });
+                "suppressed": suppressions.lock().unwrap().len(),
+                "uptime_seconds": uptime.as_secs(),
+                "max_senders": config.max_senders,
+                "cooldown_days": config.cooldown_days,
+                "strict_senders": config.strict_senders,
+                "senders": senders,
+                "persistence": persistence,
+                "tokens": valid_tokens.status()
+            });
+            if let Some(namespaces) = namespaces {
+                reply["namespaces"] = namespaces.into();
+            }
+            Ok(warp::reply::json(&reply))
+        })
+        .and_then(error::reject);
 
-    // Combine them all
-    add_route
+    // Every route is also served under /ns/{name}, for that namespace
+    let routes = add_route
         .or(addmulti_route)
         .or(dump_route)
         .or(clear_route)
         .or(status_route)
-}
+        .or(check_route)
+        .or(check_batch_route)
+        .or(add_batch_route)
+        .or(addmulti_batch_route)
+        .or(import_route)
+        .or(normalize_route)
+        .or(suppress_route)
+        .or(unsuppress_route)
+        .or(suppress_import_route)
+        .or(inbound_route)
+        .or(delete_number_route)
+        .or(delete_sender_route)
+        .or(sender_numbers_route)
+        .or(sender_stats_route)
+        .or(senders_route)
+        .or(register_sender_route)
+        .or(sender_active_route)
+        .or(delete_registered_route);
+    let ns_prefix = warp::path("ns").and(warp::path::param::<String>()).map(|_| ()).untuple_one();
 
-/// A simple wrapper for serialization/deserialization to/from JSON.
-#[derive(Serialize, Deserialize)]
-struct PersistData(HashMap<String, Vec<String>>);
-
-/// Load data from the JSON file on disk into a `HashMap`.
-///
-/// Marked `pub` so integration tests (and main) can call it.
-pub fn load_data(file_path: &str) -> HashMap<String, Vec<String>> {
-    if let Ok(json_str) = fs::read_to_string(file_path) {
-        if let Ok(parsed) = serde_json::from_str::<PersistData>(&json_str) {
-            return parsed.0;
-        }
-    }
-    HashMap::new()
-}
-
-/// Save the current store to disk as JSON.
-///
-/// Marked `pub` so integration tests (and main) can call it.
-pub fn save_data(file_path: &str, data: &HashMap<String, Vec<String>>) {
-    let wrapper = PersistData(data.clone());
-    if let Ok(json_str) = serde_json::to_string_pretty(&wrapper) {
-        let _ = fs::write(file_path, json_str);
-    }
+    // Combine them all
+    ns_prefix
+        .and(routes.clone())
+        .or(routes)
+        .recover(error::recover)
 }
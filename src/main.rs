@@ -1,40 +1,221 @@
-use std::net::TcpListener;
-use std::time::Instant;
+use std::net::{IpAddr, SocketAddr, TcpListener};
+use std::time::{Duration, Instant};
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+use clap::Parser;
 use dotenv::dotenv;
 use std::env;
+use warp::Filter;
 
 use n2o::*; // or `use crate::lib::*;` depending on naming
-use std::sync::Mutex;
+use n2o::auth::{generate_token, parse_env_tokens, watch_file, Secret};
+use n2o::config::{Cli, Command, StorageEngine};
+use std::sync::{Arc, Mutex};
 
 #[tokio::main]
 async fn main() {
     dotenv().ok();
     let start_time = Instant::now();
 
-    let chosen_port = choose_port(1337, 1338);
-    let valid_tokens = env::var("VALID_TOKENS")
-        .unwrap_or_default()
-        .split(',')
-        .map(|s| s.trim().to_string())
-        .filter(|s| !s.is_empty())
-        .collect();
+    let cli = Cli::parse();
+    if let Some(Command::GenToken { scopes }) = &cli.command {
+        gen_token(scopes);
+        return;
+    }
+    let config = Config::load(&cli).unwrap_or_else(|e| {
+        eprintln!("ERROR: {}", e);
+        std::process::exit(1);
+    });
+    if let Err(errors) = config.validate() {
+        eprintln!("ERROR: Invalid configuration:");
+        for e in errors {
+            eprintln!("  - {}", e);
+        }
+        std::process::exit(1);
+    }
+    if cli.print_config {
+        print!("{}", config.to_toml());
+        return;
+    }
+
+    env_logger::Builder::new()
+        .parse_filters(&config.log_level)
+        .init();
+
+    // Tokens in VALID_TOKENS are admin tokens; scoped tokens go in tokens_file
+    let env_tokens = parse_env_tokens(&env::var("VALID_TOKENS").unwrap_or_default()).unwrap_or_else(|e| {
+        eprintln!("ERROR: Invalid token in VALID_TOKENS: {}", e);
+        std::process::exit(1);
+    });
+    // The environment is fixed for the life of the process, so reloads only
+    // re-read tokens_file
+    let load_tokens = {
+        let config = config.clone();
+        move || -> Result<Vec<Token>, String> {
+            let mut tokens = env_tokens.clone();
+            tokens.extend(config.file_tokens()?);
+            Ok(tokens)
+        }
+    };
+    let valid_tokens = load_tokens().unwrap_or_else(|e| {
+        eprintln!("ERROR: Could not read tokens file: {}", e);
+        std::process::exit(1);
+    });
+    if valid_tokens.is_empty() {
+        log::warn!("No tokens configured; every request will be rejected.");
+    }
+    let plaintext = valid_tokens.iter().filter(|t| t.is_plaintext()).count();
+    if plaintext > 0 {
+        log::warn!("{} token(s) are configured in plaintext; `n2o gen-token` prints hashed entries.", plaintext);
+    }
+    let valid_tokens = Arc::new(TokenSet::new(valid_tokens));
+    watch_tokens(&valid_tokens, &config, load_tokens);
+
+    let ip: IpAddr = config.bind_address.parse().expect("validated bind_address");
+    let chosen_port = choose_port(ip, config.port, config.fallback_port);
+
+    // Every namespace gets its own backend, opened with its own settings
+    let addr = SocketAddr::new(ip, chosen_port);
+    match config.storage {
+        StorageEngine::Json => {
+            let namespaces = open_namespaces(&config, open_json_backend);
+            serve(namespaces, valid_tokens, start_time, config, addr).await;
+        }
+        StorageEngine::Sqlite => {
+            let namespaces = open_namespaces(&config, open_sqlite_backend);
+            serve(namespaces, valid_tokens, start_time, config, addr).await;
+        }
+    }
+}
+
+/// Opens the backend, suppression list and sender registry of every
+/// configured namespace, the backend with `open`.
+fn open_namespaces<B: StorageBackend>(config: &Config, open: impl Fn(&Config) -> B) -> Namespaces<B> {
+    let mut namespaces = Namespaces::new();
+    for name in config.namespace_names() {
+        let config = config.for_namespace(name);
+        let store: Store<B> = Arc::new(Mutex::new(open(&config)));
+        let suppressions = SuppressionList::open(config.suppression_file.clone()).unwrap_or_else(|e| {
+            eprintln!("ERROR: Could not load suppression list {}: {}", config.suppression_file, e);
+            std::process::exit(1);
+        });
+        let registry = SenderRegistry::open(config.registry_file.clone()).unwrap_or_else(|e| {
+            eprintln!("ERROR: Could not load sender registry {}: {}", config.registry_file, e);
+            std::process::exit(1);
+        });
+        namespaces.insert_with_registry(name, store, Arc::new(Mutex::new(suppressions)), Arc::new(Mutex::new(registry)));
+    }
+    namespaces
+}
+
+/// Opens the SQLite database, first migrating any JSON data file into it.
+fn open_sqlite_backend(config: &Config) -> SqliteBackend {
+    let mut backend = SqliteBackend::open(&config.sqlite_file).unwrap_or_else(|e| {
+        eprintln!("ERROR: Could not open SQLite database {}: {}", config.sqlite_file, e);
+        std::process::exit(1);
+    });
+    match backend.migrate_from_json(&config.data_file) {
+        Ok(0) => {}
+        Ok(n) => log::info!("Migrated {} numbers from {} into {}", n, config.data_file, config.sqlite_file),
+        Err(e) => {
+            eprintln!("ERROR: Could not migrate {} into {}: {}", config.data_file, config.sqlite_file, e);
+            std::process::exit(1);
+        }
+    }
+    backend
+}
+
+/// Opens the JSON data file, refusing to start on a corrupt snapshot unless
+/// `recover_from_backup` allows falling back to the newest archive.
+fn open_json_backend(config: &Config) -> JsonFileBackend {
+    let err = match JsonFileBackend::open(config.data_file.clone()) {
+        Ok(backend) => return backend,
+        Err(e) => e,
+    };
+    eprintln!("ERROR: Could not load {}: {}", config.data_file, err);
+
+    if !config.recover_from_backup {
+        eprintln!("Refusing to start. Fix or remove the file, or pass --recover-from-backup to restore the newest backup.");
+        std::process::exit(1);
+    }
 
-    // Build your store
-    let initial_data = load_data("n2o_data.json");
-    let store = Store::new(Mutex::new(initial_data));
+    match JsonFileBackend::restore_from_backup(config.data_file.clone(), &config.archive_dir, &config.archive_pattern) {
+        Ok((backend, backup)) => {
+            log::warn!("Restored {} from {}.", config.data_file, backup.display());
+            backend
+        }
+        Err(e) => {
+            eprintln!("ERROR: Could not restore from backup: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
 
+async fn serve<B: StorageBackend>(
+    namespaces: Namespaces<B>,
+    valid_tokens: Arc<TokenSet>,
+    start_time: Instant,
+    config: Config,
+    addr: SocketAddr,
+) {
     // Create routes
-    let routes = create_routes(store.clone(), valid_tokens, start_time);
+    let routes = create_routes(namespaces, valid_tokens, start_time, config)
+        .with(warp::log("n2o::http"));
 
-    println!("Listening on port {}", chosen_port);
-    warp::serve(routes).run(([0, 0, 0, 0], chosen_port)).await;
+    log::info!("Listening on {}", addr);
+    warp::serve(routes).run(addr).await;
+}
+
+/// Reloads the tokens on SIGHUP and, unless `tokens_poll_secs` is 0, whenever
+/// the tokens file changes.
+fn watch_tokens(
+    tokens: &Arc<TokenSet>,
+    config: &Config,
+    load: impl Fn() -> Result<Vec<Token>, String> + Clone + Send + 'static,
+) {
+    #[cfg(unix)]
+    {
+        let (tokens, load) = (Arc::clone(tokens), load.clone());
+        tokio::spawn(async move {
+            let mut hangup = match signal(SignalKind::hangup()) {
+                Ok(hangup) => hangup,
+                Err(e) => {
+                    log::error!("Cannot listen for SIGHUP: {}", e);
+                    return;
+                }
+            };
+            while hangup.recv().await.is_some() {
+                log::info!("SIGHUP received; reloading tokens");
+                let _ = tokens.reload(&load);
+            }
+        });
+    }
+
+    if let Some(path) = &config.tokens_file {
+        if config.tokens_poll_secs > 0 {
+            let interval = Duration::from_secs(config.tokens_poll_secs);
+            tokio::spawn(watch_file(Arc::clone(tokens), path.clone(), interval, load));
+        }
+    }
+}
+
+/// Prints a new random token and the hashed line to add to the tokens file.
+fn gen_token(scopes: &str) {
+    let token = generate_token();
+    let line = format!("{} {}", Secret::hash(&token), scopes.replace(char::is_whitespace, ""));
+    if let Err(e) = Token::parse(&line) {
+        eprintln!("ERROR: {}", e);
+        std::process::exit(1);
+    }
+    println!("Token (give this to the client; it is not stored): {}", token);
+    println!("Tokens file line: {}", line);
 }
 
-fn choose_port(primary: u16, fallback: u16) -> u16 {
-    match TcpListener::bind(("0.0.0.0", primary)) {
-        Ok(_) => primary,
-        Err(_) => {
-            eprintln!("WARNING: Port {} is in use; switching to {}.", primary, fallback);
+fn choose_port(ip: IpAddr, primary: u16, fallback: Option<u16>) -> u16 {
+    match (TcpListener::bind((ip, primary)), fallback) {
+        (Ok(_), _) | (Err(_), None) => primary,
+        (Err(_), Some(fallback)) => {
+            log::warn!("Port {} is in use; switching to {}.", primary, fallback);
             fallback
         }
     }
@@ -0,0 +1,121 @@
+// src/namespace.rs
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use crate::registry::{Registry, SenderRegistry};
+use crate::suppression::{SuppressionList, Suppressions};
+use crate::{Store, StorageBackend};
+
+/// The namespace served without a `/ns/{name}` prefix. It uses the top-level
+/// settings, so a server without namespaces behaves as before.
+pub const DEFAULT_NAMESPACE: &str = "default";
+
+/// Longest accepted namespace name.
+pub const MAX_NAME_LEN: usize = 64;
+
+/// Per-namespace overrides of the top-level settings, from a
+/// `[namespaces.<name>]` table. Unset files are derived from the top-level
+/// ones and the namespace name (see `Config::for_namespace`).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NamespaceConfig {
+    pub data_file: Option<String>,
+    pub sqlite_file: Option<String>,
+    pub suppression_file: Option<String>,
+    pub audit_file: Option<String>,
+    pub registry_file: Option<String>,
+    pub archive_dir: Option<String>,
+    pub max_senders: Option<usize>,
+    pub cooldown_days: Option<u32>,
+    pub strict_senders: Option<bool>,
+}
+
+/// Whether `name` can be used as a namespace: lowercase ASCII letters,
+/// digits, `-` and `_`, so it is safe in paths and file names.
+pub fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && name.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-' || b == b'_')
+}
+
+/// Inserts the namespace name before a file's extension:
+/// `n2o_data.json` becomes `n2o_data.acme.json`.
+pub fn namespaced_file(file: &str, name: &str) -> String {
+    let path = Path::new(file);
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or(file);
+    let file_name = match path.extension().and_then(|e| e.to_str()) {
+        Some(ext) => format!("{}.{}.{}", stem, name, ext),
+        None => format!("{}.{}", stem, name),
+    };
+    path.with_file_name(file_name).to_string_lossy().into_owned()
+}
+
+/// The namespace named by a request path of the form `/ns/{name}/...`.
+pub fn from_path(path: &str) -> Option<&str> {
+    let rest = path.strip_prefix("/ns/")?;
+    Some(rest.split('/').next().unwrap_or(rest))
+}
+
+/// The store, suppression list and sender registry of every namespace, by
+/// name.
+pub struct Namespaces<B> {
+    stores: BTreeMap<String, (Store<B>, Suppressions, Registry)>,
+}
+
+impl<B: StorageBackend> Namespaces<B> {
+    pub fn new() -> Self {
+        Namespaces { stores: BTreeMap::new() }
+    }
+
+    /// Adds a namespace with an empty, in-memory suppression list and
+    /// sender registry.
+    pub fn insert(&mut self, name: impl Into<String>, store: Store<B>) {
+        let suppressions = Arc::new(Mutex::new(SuppressionList::in_memory()));
+        self.insert_with_suppressions(name, store, suppressions);
+    }
+
+    /// Adds a namespace with an empty, in-memory sender registry.
+    pub fn insert_with_suppressions(&mut self, name: impl Into<String>, store: Store<B>, suppressions: Suppressions) {
+        let registry = Arc::new(Mutex::new(SenderRegistry::in_memory()));
+        self.insert_with_registry(name, store, suppressions, registry);
+    }
+
+    pub fn insert_with_registry(
+        &mut self,
+        name: impl Into<String>,
+        store: Store<B>,
+        suppressions: Suppressions,
+        registry: Registry,
+    ) {
+        self.stores.insert(name.into(), (store, suppressions, registry));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Store<B>> {
+        self.stores.get(name).map(|(store, _, _)| store)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Store<B>, &Suppressions, &Registry)> {
+        self.stores
+            .iter()
+            .map(|(name, (store, suppressions, registry))| (name.as_str(), store, suppressions, registry))
+    }
+}
+
+impl<B: StorageBackend> Default for Namespaces<B> {
+    fn default() -> Self {
+        Namespaces::new()
+    }
+}
+
+/// A single store is the default namespace.
+impl<B: StorageBackend> From<Store<B>> for Namespaces<B> {
+    fn from(store: Store<B>) -> Self {
+        let mut namespaces = Namespaces::new();
+        namespaces.insert(DEFAULT_NAMESPACE, store);
+        namespaces
+    }
+}
@@ -0,0 +1,137 @@
+// src/normalize.rs
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::{Config, NumberFormat};
+use crate::letter_to_digit;
+use crate::validation::{check_nanp, to_e164, ErrorCode};
+
+/// Digit counts accepted as SMS short codes.
+pub const SHORT_CODE_DIGITS: std::ops::RangeInclusive<usize> = 5..=6;
+
+/// Optional steps applied to a number before it is validated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NormalizationPolicy {
+    /// Translate letters to keypad digits (`1-800-FLOWERS`). When off, a
+    /// number containing letters is rejected.
+    pub vanity_letters: bool,
+    /// Drop a trailing extension written as `x89`, `ext. 89` or `#89`.
+    pub strip_extensions: bool,
+    /// Accept 5–6 digit SMS short codes as senders, stored as-is.
+    pub short_code_senders: bool,
+}
+
+impl Default for NormalizationPolicy {
+    fn default() -> Self {
+        NormalizationPolicy { vanity_letters: true, strip_extensions: true, short_code_senders: false }
+    }
+}
+
+/// What a number is used as. Short codes can only ever be senders.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// The number being texted (`key`).
+    Recipient,
+    /// The number texting it (`val`).
+    Sender,
+}
+
+/// One transformation applied by `normalize`, for `/normalize`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Step {
+    pub step: &'static str,
+    /// The number after this step.
+    pub value: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+/// The outcome of `normalize`: every step taken, and the stored form or
+/// the reason (and any partially normalized value) it was rejected.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Normalization {
+    pub steps: Vec<Step>,
+    pub result: Result<String, (ErrorCode, Option<String>)>,
+}
+
+/// Normalizes `raw` under the configured number format and policy.
+pub fn normalize(raw: &str, role: Role, config: &Config) -> Normalization {
+    let policy = &config.normalization;
+    let mut steps = Vec::new();
+    let mut push = |step, value: &str, detail: Option<String>| {
+        steps.push(Step { step, value: value.to_string(), detail });
+    };
+
+    let mut number = raw.trim().to_string();
+    push("trim", &number, None);
+
+    if policy.strip_extensions {
+        if let Some(at) = extension_start(&number) {
+            let extension = number.split_off(at);
+            push("strip_extension", &number, Some(format!("removed '{}'", extension.trim())));
+        }
+    }
+
+    if number.chars().any(|c| c.is_ascii_alphabetic()) {
+        if !policy.vanity_letters {
+            return Normalization { steps, result: Err((ErrorCode::ContainsLetters, None)) };
+        }
+        number = number.chars().map(|c| letter_to_digit(c).unwrap_or(c)).collect();
+        push("vanity_letters", &number, None);
+    }
+
+    let digits: String = number.chars().filter(char::is_ascii_digit).collect();
+    if role == Role::Sender && SHORT_CODE_DIGITS.contains(&digits.len()) && !number.starts_with('+') {
+        let result = if policy.short_code_senders {
+            push("short_code", &digits, None);
+            Ok(digits)
+        } else {
+            Err((ErrorCode::ShortCodeNotAllowed, Some(digits)))
+        };
+        return Normalization { steps, result };
+    }
+
+    let result = match config.number_format {
+        NumberFormat::TenDigit => {
+            push("digits_only", &digits, None);
+            let number = if digits.len() > 10 {
+                let last_ten = digits[digits.len() - 10..].to_string();
+                push("last_ten_digits", &last_ten, None);
+                last_ten
+            } else {
+                digits
+            };
+            check_nanp(&number).map(|()| number.clone()).map_err(|code| (code, Some(number)))
+        }
+        NumberFormat::E164 => match to_e164(&number, config.country()) {
+            Ok(e164) => {
+                push("e164", &e164, Some(format!("default country {}", config.default_country)));
+                Ok(e164)
+            }
+            Err(normalized) => Err((ErrorCode::InvalidNumber, normalized)),
+        },
+    };
+    Normalization { steps, result }
+}
+
+/// Finds where a trailing extension (`x89`, `ext. 89`, `extension 89`,
+/// `#89`) starts, including any spaces before it. The marker must follow a
+/// digit or `)` so that vanity numbers such as `1-800-BOX-1234` are left alone.
+fn extension_start(number: &str) -> Option<usize> {
+    let lower = number.to_ascii_lowercase();
+    for marker in ["extension", "ext.", "ext", "x", "#"] {
+        let at = match lower.rfind(marker) {
+            Some(at) => at,
+            None => continue,
+        };
+        let before = lower[..at].trim_end();
+        let after = lower[at + marker.len()..].trim_start();
+        let follows_number = before.ends_with(|c: char| c.is_ascii_digit() || c == ')');
+        if follows_number && !after.is_empty() && after.chars().all(|c| c.is_ascii_digit()) {
+            return Some(before.len());
+        }
+    }
+    None
+}
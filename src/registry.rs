@@ -0,0 +1,141 @@
+// src/registry.rs
+
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use crate::storage::write_atomic;
+
+/// What is known about a registered sender, as set with `PUT /senders/{val}`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RegisteredSender {
+    /// A display name, e.g. the team or agent using the number.
+    pub label: Option<String>,
+    pub campaign: Option<String>,
+    /// A disabled sender stays registered but is refused in strict mode.
+    pub active: bool,
+    /// Most numbers the sender may contact per UTC day in strict mode;
+    /// unset means no cap.
+    pub daily_cap: Option<u32>,
+}
+
+impl Default for RegisteredSender {
+    fn default() -> Self {
+        RegisteredSender { label: None, campaign: None, active: true, daily_cap: None }
+    }
+}
+
+/// The senders known to a namespace, kept apart from the main store like the
+/// suppression list. Only consulted by `/addmulti` when `strict_senders` is
+/// set.
+///
+/// Registries are small and change rarely, so `flush` rewrites the whole file.
+/// The per-day contact counts behind `daily_cap` are kept in memory only;
+/// they are seeded from the store with `set_contacts`.
+#[derive(Debug, Default)]
+pub struct SenderRegistry {
+    /// Where the registry is persisted; `None` keeps it in memory only.
+    path: Option<String>,
+    entries: BTreeMap<String, RegisteredSender>,
+    /// Numbers each registered sender has contacted, and on which UTC day.
+    contacts: HashMap<String, (NaiveDate, usize)>,
+}
+
+/// The sender registry shared by the routes of one namespace.
+pub type Registry = Arc<Mutex<SenderRegistry>>;
+
+impl SenderRegistry {
+    /// An empty registry that is never written to disk.
+    pub fn in_memory() -> Self {
+        SenderRegistry::default()
+    }
+
+    /// Loads the registry at `path`; a missing file is an empty registry.
+    pub fn open(path: impl Into<String>) -> std::io::Result<Self> {
+        let path = path.into();
+        let entries = match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).map_err(std::io::Error::other)?,
+            Err(e) if e.kind() == ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(SenderRegistry { path: Some(path), entries, contacts: HashMap::new() })
+    }
+
+    pub fn get(&self, sender: &str) -> Option<&RegisteredSender> {
+        self.entries.get(sender)
+    }
+
+    /// Registers `sender`, replacing its settings if it already was; returns
+    /// the previous ones.
+    pub fn register(&mut self, sender: String, settings: RegisteredSender) -> Option<RegisteredSender> {
+        self.entries.insert(sender, settings)
+    }
+
+    /// Enables or disables `sender`. Returns `None` if it is not registered.
+    pub fn set_active(&mut self, sender: &str, active: bool) -> Option<&RegisteredSender> {
+        let entry = self.entries.get_mut(sender)?;
+        entry.active = active;
+        Some(entry)
+    }
+
+    /// Removes `sender`, returning its settings if it was registered.
+    pub fn remove(&mut self, sender: &str) -> Option<RegisteredSender> {
+        self.contacts.remove(sender);
+        self.entries.remove(sender)
+    }
+
+    /// Sets how many numbers `sender` contacted on `day`, as counted from the
+    /// store when the sender is registered or the server starts.
+    pub fn set_contacts(&mut self, sender: &str, day: NaiveDate, count: usize) {
+        self.contacts.insert(sender.to_string(), (day, count));
+    }
+
+    /// Counts one more number contacted by `sender` on `day`. Only registered
+    /// senders are counted.
+    pub fn record_contact(&mut self, sender: &str, day: NaiveDate) {
+        if !self.entries.contains_key(sender) {
+            return;
+        }
+        match self.contacts.get_mut(sender) {
+            Some((counted, count)) if *counted == day => *count += 1,
+            _ => self.set_contacts(sender, day, 1),
+        }
+    }
+
+    /// How many numbers `sender` has contacted on `day`.
+    pub fn contacts_on(&self, sender: &str, day: NaiveDate) -> usize {
+        match self.contacts.get(sender) {
+            Some(&(counted, count)) if counted == day => count,
+            _ => 0,
+        }
+    }
+
+    /// Every registered sender, ordered by number.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &RegisteredSender)> {
+        self.entries.iter().map(|(sender, settings)| (sender.as_str(), settings))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Writes the registry to its file, if it has one.
+    pub fn flush(&self) -> std::io::Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let json = serde_json::to_string_pretty(&self.entries).map_err(std::io::Error::other)?;
+        write_atomic(Path::new(path), json.as_bytes())
+    }
+}
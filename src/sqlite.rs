@@ -0,0 +1,341 @@
+// src/sqlite.rs
+
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+
+use crate::storage::{load_data, SenderRecord, StorageBackend};
+
+/// Tables created on first open. Timestamps are Unix seconds (UTC) so they
+/// can be queried with SQLite's `'unixepoch'` date modifier. A sender's
+/// `created_at` is its first contact with the key.
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS numbers (
+        key        TEXT PRIMARY KEY,
+        created_at INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS senders (
+        id         INTEGER PRIMARY KEY AUTOINCREMENT,
+        key        TEXT NOT NULL,
+        sender     TEXT NOT NULL,
+        created_at INTEGER NOT NULL,
+        last_seen  INTEGER NOT NULL,
+        contact_count INTEGER NOT NULL DEFAULT 1,
+        UNIQUE (key, sender)
+    );
+    CREATE INDEX IF NOT EXISTS senders_by_sender ON senders (sender);
+    CREATE TABLE IF NOT EXISTS meta (
+        name  TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+";
+
+/// Columns added to `senders` after its first release, with the definitions
+/// used to add them to older databases.
+const SENDER_UPGRADES: &[(&str, &str)] = &[
+    ("last_seen", "last_seen INTEGER NOT NULL DEFAULT 0"),
+    ("contact_count", "contact_count INTEGER NOT NULL DEFAULT 1"),
+];
+
+const SENDER_COLUMNS: &str = "sender, created_at, last_seen, contact_count";
+
+/// The `meta` row recording that a JSON data file has been ingested.
+const JSON_MIGRATION_MARKER: &str = "json_migrated_from";
+
+/// A durable engine backed by an embedded SQLite database.
+///
+/// Changes are made inside a transaction that is opened by the first
+/// mutation and committed by `flush`, so a batch of changes followed by one
+/// flush lands atomically. Because the trait's mutating methods cannot fail,
+/// the first SQL error is held and returned from the next `flush`, which
+/// rolls the transaction back. Reads return their errors directly.
+pub struct SqliteBackend {
+    conn: Connection,
+    in_transaction: bool,
+    error: Option<rusqlite::Error>,
+}
+
+impl SqliteBackend {
+    /// Opens (or creates) the database at `path`.
+    pub fn open(path: &str) -> rusqlite::Result<Self> {
+        Self::from_connection(Connection::open(path)?)
+    }
+
+    /// Opens a private in-memory database, mostly useful for tests.
+    pub fn open_in_memory() -> rusqlite::Result<Self> {
+        Self::from_connection(Connection::open_in_memory()?)
+    }
+
+    fn from_connection(conn: Connection) -> rusqlite::Result<Self> {
+        conn.pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(()))?;
+        conn.pragma_update(None, "synchronous", "FULL")?;
+        conn.execute_batch(SCHEMA)?;
+        upgrade_senders(&conn)?;
+        Ok(SqliteBackend { conn, in_transaction: false, error: None })
+    }
+
+    /// The underlying connection, for ad-hoc SQL queries.
+    pub fn connection(&self) -> &Connection {
+        &self.conn
+    }
+
+    /// One-shot migration from a JSON data file written by `save_data`.
+    ///
+    /// Ingests the file (and its journal) the first time it is called for a
+    /// database and records that in the `meta` table; later calls, or a
+    /// missing file, import nothing. A corrupt file is an error and nothing
+    /// is recorded. Returns the number of keys imported.
+    pub fn migrate_from_json(&mut self, json_path: &str) -> std::io::Result<usize> {
+        let migrated: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT value FROM meta WHERE name = ?1",
+                params![JSON_MIGRATION_MARKER],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(std::io::Error::other)?;
+        if migrated.is_some() {
+            return Ok(0);
+        }
+
+        let data = load_data(json_path)?;
+        let now = Utc::now();
+        let mut import = || -> rusqlite::Result<()> {
+            let tx = self.conn.transaction()?;
+            for (key, senders) in &data {
+                // A number was created by its first contact
+                let created_at = senders.iter().map(|r| r.first_seen).min().unwrap_or(now);
+                tx.execute(
+                    "INSERT OR IGNORE INTO numbers (key, created_at) VALUES (?1, ?2)",
+                    params![key, created_at.timestamp()],
+                )?;
+                for record in senders {
+                    insert_record(&tx, key, record)?;
+                }
+            }
+            tx.execute(
+                "INSERT INTO meta (name, value) VALUES (?1, ?2)",
+                params![JSON_MIGRATION_MARKER, json_path],
+            )?;
+            tx.commit()
+        };
+        import().map_err(std::io::Error::other)?;
+
+        Ok(data.len())
+    }
+
+    /// Runs a mutation inside the open transaction, holding on to the first error.
+    fn write(&mut self, f: impl FnOnce(&Connection) -> rusqlite::Result<()>) {
+        if self.error.is_some() {
+            return;
+        }
+        let result = if self.in_transaction {
+            Ok(())
+        } else {
+            self.conn.execute_batch("BEGIN IMMEDIATE")
+        }
+        .and_then(|_| {
+            self.in_transaction = true;
+            f(&self.conn)
+        });
+        if let Err(e) = result {
+            self.error = Some(e);
+        }
+    }
+
+    fn senders_of(&self, key: &str) -> rusqlite::Result<Option<Vec<SenderRecord>>> {
+        let exists = self
+            .conn
+            .query_row("SELECT 1 FROM numbers WHERE key = ?1", params![key], |_| Ok(()))
+            .optional()?;
+        if exists.is_none() {
+            return Ok(None);
+        }
+
+        let mut stmt = self.conn.prepare_cached(&format!(
+            "SELECT {} FROM senders WHERE key = ?1 ORDER BY id",
+            SENDER_COLUMNS
+        ))?;
+        let senders = stmt
+            .query_map(params![key], |row| sender_record(row, 0))?
+            .collect::<rusqlite::Result<Vec<SenderRecord>>>()?;
+        Ok(Some(senders))
+    }
+
+    fn all(&self) -> rusqlite::Result<Vec<(String, Vec<SenderRecord>)>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT n.key, s.sender, s.created_at, s.last_seen, s.contact_count FROM numbers n
+             LEFT JOIN senders s ON s.key = n.key
+             ORDER BY n.key, s.id",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            let sender = match row.get::<_, Option<String>>(1)? {
+                Some(_) => Some(sender_record(row, 1)?),
+                None => None,
+            };
+            Ok((row.get::<_, String>(0)?, sender))
+        })?;
+
+        let mut entries: Vec<(String, Vec<SenderRecord>)> = Vec::new();
+        for row in rows {
+            let (key, sender) = row?;
+            if entries.last().map(|(k, _)| k != &key).unwrap_or(true) {
+                entries.push((key, Vec::new()));
+            }
+            if let (Some(sender), Some((_, senders))) = (sender, entries.last_mut()) {
+                senders.push(sender);
+            }
+        }
+        Ok(entries)
+    }
+}
+
+/// Adds any `SENDER_UPGRADES` columns an older database lacks. Existing rows
+/// get `last_seen` equal to their first contact.
+fn upgrade_senders(conn: &Connection) -> rusqlite::Result<()> {
+    let mut stmt = conn.prepare("SELECT name FROM pragma_table_info('senders')")?;
+    let columns = stmt
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<String>>>()?;
+    for (column, definition) in SENDER_UPGRADES {
+        if !columns.iter().any(|c| c == column) {
+            conn.execute_batch(&format!("ALTER TABLE senders ADD COLUMN {}", definition))?;
+            if *column == "last_seen" {
+                conn.execute_batch("UPDATE senders SET last_seen = created_at")?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Inserts a sender record, replacing the key's existing record for that sender.
+fn insert_record(conn: &Connection, key: &str, record: &SenderRecord) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO senders (key, sender, created_at, last_seen, contact_count)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![
+            key,
+            record.sender,
+            record.first_seen.timestamp(),
+            record.last_seen.timestamp(),
+            record.count as i64
+        ],
+    )?;
+    Ok(())
+}
+
+/// Reads a `SenderRecord` from the `SENDER_COLUMNS` starting at column `start`.
+fn sender_record(row: &Row, start: usize) -> rusqlite::Result<SenderRecord> {
+    let time = |idx: usize| -> rusqlite::Result<DateTime<Utc>> {
+        let secs: i64 = row.get(idx)?;
+        Ok(DateTime::from_timestamp(secs, 0).unwrap_or_default())
+    };
+    Ok(SenderRecord {
+        sender: row.get(start)?,
+        first_seen: time(start + 1)?,
+        last_seen: time(start + 2)?,
+        count: row.get::<_, i64>(start + 3)? as u64,
+    })
+}
+
+impl StorageBackend for SqliteBackend {
+    fn get(&self, key: &str) -> std::io::Result<Option<Vec<SenderRecord>>> {
+        self.senders_of(key).map_err(std::io::Error::other)
+    }
+
+    fn insert(&mut self, key: String, senders: Vec<SenderRecord>) {
+        let now = Utc::now().timestamp();
+        self.write(|conn| {
+            conn.execute("DELETE FROM senders WHERE key = ?1", params![key])?;
+            conn.execute(
+                "INSERT OR IGNORE INTO numbers (key, created_at) VALUES (?1, ?2)",
+                params![key, now],
+            )?;
+            for record in &senders {
+                insert_record(conn, &key, record)?;
+            }
+            Ok(())
+        });
+    }
+
+    fn add_sender(&mut self, key: &str, sender: String, at: DateTime<Utc>) {
+        let at = at.timestamp();
+        self.write(|conn| {
+            conn.execute(
+                "INSERT OR IGNORE INTO numbers (key, created_at) VALUES (?1, ?2)",
+                params![key, at],
+            )?;
+            conn.execute(
+                "INSERT INTO senders (key, sender, created_at, last_seen, contact_count)
+                 VALUES (?1, ?2, ?3, ?3, 1)
+                 ON CONFLICT (key, sender) DO UPDATE SET
+                     last_seen = MAX(last_seen, excluded.last_seen),
+                     contact_count = contact_count + 1",
+                params![key, sender, at],
+            )?;
+            Ok(())
+        });
+    }
+
+    fn remove(&mut self, key: &str) -> Option<Vec<SenderRecord>> {
+        let removed = match self.senders_of(key) {
+            Ok(removed) => removed,
+            Err(e) => {
+                self.error.get_or_insert(e);
+                return None;
+            }
+        };
+        if removed.is_some() {
+            self.write(|conn| {
+                conn.execute("DELETE FROM senders WHERE key = ?1", params![key])?;
+                conn.execute("DELETE FROM numbers WHERE key = ?1", params![key])?;
+                Ok(())
+            });
+        }
+        removed
+    }
+
+    fn iter(&self) -> std::io::Result<Box<dyn Iterator<Item = (String, Vec<SenderRecord>)> + '_>> {
+        let entries = self.all().map_err(std::io::Error::other)?;
+        Ok(Box::new(entries.into_iter()))
+    }
+
+    /// Served by the `senders_by_sender` index.
+    fn recipients_of(&self, sender: &str) -> std::io::Result<Vec<(String, SenderRecord)>> {
+        let query = || -> rusqlite::Result<Vec<(String, SenderRecord)>> {
+            let mut stmt = self.conn.prepare_cached(&format!(
+                "SELECT key, {} FROM senders WHERE sender = ?1 ORDER BY key",
+                SENDER_COLUMNS
+            ))?;
+            let rows = stmt.query_map(params![sender], |row| Ok((row.get(0)?, sender_record(row, 1)?)))?;
+            rows.collect()
+        };
+        query().map_err(std::io::Error::other)
+    }
+
+    fn clear(&mut self) {
+        self.write(|conn| conn.execute_batch("DELETE FROM senders; DELETE FROM numbers;"));
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        let in_transaction = std::mem::take(&mut self.in_transaction);
+        let result = match self.error.take() {
+            Some(e) => {
+                if in_transaction {
+                    let _ = self.conn.execute_batch("ROLLBACK");
+                }
+                Err(e)
+            }
+            None if in_transaction => self.conn.execute_batch("COMMIT"),
+            None => Ok(()),
+        };
+        result.map_err(std::io::Error::other)
+    }
+
+    fn len(&self) -> std::io::Result<usize> {
+        self.conn
+            .query_row("SELECT COUNT(*) FROM numbers", [], |row| row.get::<_, i64>(0))
+            .map(|n| n as usize)
+            .map_err(std::io::Error::other)
+    }
+}
@@ -0,0 +1,492 @@
+// src/storage.rs
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::config::TIMESTAMP_PLACEHOLDER;
+use crate::journal::{self, JournalEvent};
+
+use flate2::read::GzDecoder;
+
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+/// One sender's contact history with a number.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "SenderRepr")]
+pub struct SenderRecord {
+    /// The sender's normalized number.
+    pub sender: String,
+    /// When this sender first contacted the number.
+    pub first_seen: DateTime<Utc>,
+    /// When this sender last contacted the number.
+    pub last_seen: DateTime<Utc>,
+    /// How many times this sender has contacted the number.
+    pub count: u64,
+}
+
+impl SenderRecord {
+    /// A record for a sender's first contact at `at`.
+    pub fn new(sender: impl Into<String>, at: DateTime<Utc>) -> Self {
+        SenderRecord { sender: sender.into(), first_seen: at, last_seen: at, count: 1 }
+    }
+}
+
+impl PartialEq<str> for SenderRecord {
+    fn eq(&self, other: &str) -> bool {
+        self.sender == other
+    }
+}
+
+impl PartialEq<&str> for SenderRecord {
+    fn eq(&self, other: &&str) -> bool {
+        self.sender == *other
+    }
+}
+
+/// What a sender looks like on disk: a full record, or a bare sender string
+/// written before timestamps were tracked.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SenderRepr {
+    Legacy(String),
+    Full {
+        sender: String,
+        first_seen: DateTime<Utc>,
+        last_seen: DateTime<Utc>,
+        count: u64,
+    },
+}
+
+impl From<SenderRepr> for SenderRecord {
+    /// Legacy entries carry no timestamps, so they are dated to the Unix
+    /// epoch. A fixed date loads the same way every time, never holds a
+    /// number in cooldown, and sorts before any journaled contact, so replay
+    /// still applies those on top.
+    fn from(repr: SenderRepr) -> Self {
+        match repr {
+            SenderRepr::Legacy(sender) => SenderRecord::new(sender, DateTime::UNIX_EPOCH),
+            SenderRepr::Full { sender, first_seen, last_seen, count } => {
+                SenderRecord { sender, first_seen, last_seen, count }
+            }
+        }
+    }
+}
+
+/// Records a contact from `sender` at `at` in a key's sender list: bumps the
+/// existing record, or appends a new one.
+pub fn record_contact(records: &mut Vec<SenderRecord>, sender: String, at: DateTime<Utc>) {
+    match records.iter_mut().find(|r| r.sender == sender) {
+        Some(record) => {
+            record.last_seen = record.last_seen.max(at);
+            record.count += 1;
+        }
+        None => records.push(SenderRecord::new(sender, at)),
+    }
+}
+
+/// The operations every storage engine behind a `Store` must support.
+///
+/// Routes only talk to the store through this trait, so swapping the JSON
+/// file for another engine does not touch any route code. Mutating methods
+/// are infallible and only change the engine's working state; `flush` is
+/// where the engine persists them and reports I/O errors. Reads report their
+/// errors directly, so a failed read is never mistaken for a missing key or
+/// an empty store.
+pub trait StorageBackend: Send + 'static {
+    /// Returns the senders recorded for `key`, if any.
+    fn get(&self, key: &str) -> std::io::Result<Option<Vec<SenderRecord>>>;
+
+    /// Inserts `key` with the given senders, replacing any existing entry.
+    fn insert(&mut self, key: String, senders: Vec<SenderRecord>);
+
+    /// Records a contact from `sender` to `key` at `at`, creating the key
+    /// and/or the sender's record as needed (see `record_contact`).
+    fn add_sender(&mut self, key: &str, sender: String, at: DateTime<Utc>);
+
+    /// Removes `key` and returns the senders it had. An engine that cannot
+    /// read them holds the error for `flush`, like a failed mutation.
+    fn remove(&mut self, key: &str) -> Option<Vec<SenderRecord>>;
+
+    /// Iterates over every key and its senders, in no particular order.
+    fn iter(&self) -> std::io::Result<Box<dyn Iterator<Item = (String, Vec<SenderRecord>)> + '_>>;
+
+    /// Returns every key `sender` has contacted, with its record there,
+    /// ordered by key.
+    ///
+    /// The default scans every key; engines that keep a reverse index
+    /// override it.
+    fn recipients_of(&self, sender: &str) -> std::io::Result<Vec<(String, SenderRecord)>> {
+        let mut recipients: Vec<(String, SenderRecord)> = self
+            .iter()?
+            .filter_map(|(key, senders)| Some((key, senders.into_iter().find(|r| r.sender == sender)?)))
+            .collect();
+        recipients.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(recipients)
+    }
+
+    /// Removes every key.
+    fn clear(&mut self);
+
+    /// Persists any pending changes. Whether a failed flush leaves them
+    /// pending for the next one is up to the engine.
+    fn flush(&mut self) -> std::io::Result<()>;
+
+    /// Returns the number of keys.
+    fn len(&self) -> std::io::Result<usize> {
+        Ok(self.iter()?.count())
+    }
+
+    /// Returns `true` if no keys are stored.
+    fn is_empty(&self) -> std::io::Result<bool> {
+        Ok(self.len()? == 0)
+    }
+}
+
+/// An in-memory-only engine: nothing is written to disk.
+pub type MemoryBackend = HashMap<String, Vec<SenderRecord>>;
+
+impl StorageBackend for MemoryBackend {
+    fn get(&self, key: &str) -> std::io::Result<Option<Vec<SenderRecord>>> {
+        Ok(HashMap::get(self, key).cloned())
+    }
+
+    fn insert(&mut self, key: String, senders: Vec<SenderRecord>) {
+        HashMap::insert(self, key, senders);
+    }
+
+    fn add_sender(&mut self, key: &str, sender: String, at: DateTime<Utc>) {
+        record_contact(self.entry(key.to_string()).or_default(), sender, at);
+    }
+
+    fn remove(&mut self, key: &str) -> Option<Vec<SenderRecord>> {
+        HashMap::remove(self, key)
+    }
+
+    fn iter(&self) -> std::io::Result<Box<dyn Iterator<Item = (String, Vec<SenderRecord>)> + '_>> {
+        Ok(Box::new(HashMap::iter(self).map(|(k, v)| (k.clone(), v.clone()))))
+    }
+
+    fn clear(&mut self) {
+        HashMap::clear(self);
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn len(&self) -> std::io::Result<usize> {
+        Ok(HashMap::len(self))
+    }
+}
+
+/// How many journal events `JsonFileBackend` accumulates before compacting
+/// them into a fresh snapshot.
+pub const DEFAULT_COMPACT_THRESHOLD: usize = 10_000;
+
+/// The original engine: an in-memory map persisted to a JSON snapshot plus
+/// an append-only journal of changes made since that snapshot.
+///
+/// `flush` only appends the pending events to `<path>.wal`, so a write costs
+/// the size of the change rather than the size of the dataset. Once the
+/// journal holds `compact_threshold` events it is folded into a new snapshot
+/// and emptied.
+///
+/// A reverse index from each sender to the keys it contacted is kept in
+/// memory alongside the data and rebuilt on open.
+pub struct JsonFileBackend {
+    path: String,
+    data: HashMap<String, Vec<SenderRecord>>,
+    by_sender: HashMap<String, BTreeSet<String>>,
+    pending: Vec<JournalEvent>,
+    journal_len: usize,
+    compact_threshold: usize,
+}
+
+impl JsonFileBackend {
+    /// Opens the engine, loading the snapshot at `path` and replaying its journal.
+    ///
+    /// Fails if the snapshot exists but cannot be read or parsed, or if the
+    /// journal is corrupt anywhere but in its torn last line.
+    pub fn open(path: impl Into<String>) -> std::io::Result<Self> {
+        let path = path.into();
+        let mut data = load_snapshot(&path)?;
+        let journal_len = journal::recover(&journal::journal_path(&path), &mut data)?;
+        let mut backend = JsonFileBackend {
+            path,
+            data: HashMap::new(),
+            by_sender: HashMap::new(),
+            pending: Vec::new(),
+            journal_len,
+            compact_threshold: DEFAULT_COMPACT_THRESHOLD,
+        };
+        for (key, senders) in &data {
+            backend.index(key, senders);
+        }
+        backend.data = data;
+        Ok(backend)
+    }
+
+    /// Replaces a corrupt snapshot at `path` with the newest archive in
+    /// `backup_dir` matching `pattern`, then opens the engine as usual.
+    ///
+    /// The corrupt file, and the journal if it is the corrupt one, is kept
+    /// next to the original with a `.corrupt-<timestamp>` suffix for
+    /// inspection. Returns the engine and the archive it was restored from.
+    pub fn restore_from_backup(
+        path: impl Into<String>,
+        backup_dir: &str,
+        pattern: &str,
+    ) -> std::io::Result<(Self, PathBuf)> {
+        let path = path.into();
+        let backup = newest_backup(backup_dir, pattern).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("no archive matching {} in {}", pattern, backup_dir),
+            )
+        })?;
+        let data = load_backup(&backup)?;
+
+        let timestamp = chrono::Local::now().format("%Y%m%d%H%M%S");
+        if Path::new(&path).exists() {
+            fs::rename(&path, format!("{}.corrupt-{}", path, timestamp))?;
+        }
+        // A corrupt journal is set aside the same way
+        let journal = journal::journal_path(&path);
+        if journal::replay(&journal, &mut HashMap::new()).is_err() {
+            fs::rename(&journal, format!("{}.corrupt-{}", journal, timestamp))?;
+        }
+        save_data(&path, &data)?;
+
+        Ok((Self::open(path)?, backup))
+    }
+
+    /// Sets how many journal events trigger a compaction.
+    pub fn with_compact_threshold(mut self, threshold: usize) -> Self {
+        self.compact_threshold = threshold.max(1);
+        self
+    }
+
+    /// The JSON snapshot file this engine persists to.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Writes the full dataset as a new snapshot and empties the journal.
+    pub fn compact(&mut self) -> std::io::Result<()> {
+        self.flush_pending()?;
+        save_data(&self.path, &self.data)?;
+        journal::truncate(&journal::journal_path(&self.path))?;
+        self.journal_len = 0;
+        Ok(())
+    }
+
+    /// Adds `key` to the reverse index entry of each of `senders`.
+    fn index(&mut self, key: &str, senders: &[SenderRecord]) {
+        for record in senders {
+            self.by_sender.entry(record.sender.clone()).or_default().insert(key.to_string());
+        }
+    }
+
+    /// Removes `key` from the reverse index entry of each of `senders`.
+    fn unindex(&mut self, key: &str, senders: &[SenderRecord]) {
+        for record in senders {
+            if let Some(keys) = self.by_sender.get_mut(&record.sender) {
+                keys.remove(key);
+                if keys.is_empty() {
+                    self.by_sender.remove(&record.sender);
+                }
+            }
+        }
+    }
+
+    fn flush_pending(&mut self) -> std::io::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        journal::append_events(&journal::journal_path(&self.path), &self.pending)?;
+        self.journal_len += self.pending.len();
+        self.pending.clear();
+        Ok(())
+    }
+}
+
+impl StorageBackend for JsonFileBackend {
+    fn get(&self, key: &str) -> std::io::Result<Option<Vec<SenderRecord>>> {
+        Ok(self.data.get(key).cloned())
+    }
+
+    fn insert(&mut self, key: String, senders: Vec<SenderRecord>) {
+        if let Some(previous) = self.data.insert(key.clone(), senders.clone()) {
+            self.unindex(&key, &previous);
+        }
+        self.index(&key, &senders);
+        self.pending.push(JournalEvent::Insert { key, senders });
+    }
+
+    fn add_sender(&mut self, key: &str, sender: String, at: DateTime<Utc>) {
+        record_contact(self.data.entry(key.to_string()).or_default(), sender.clone(), at);
+        self.by_sender.entry(sender.clone()).or_default().insert(key.to_string());
+        self.pending.push(JournalEvent::AddSender { key: key.to_string(), sender, at });
+    }
+
+    fn remove(&mut self, key: &str) -> Option<Vec<SenderRecord>> {
+        let removed = self.data.remove(key);
+        if let Some(senders) = &removed {
+            self.unindex(key, senders);
+            self.pending.push(JournalEvent::Remove { key: key.to_string() });
+        }
+        removed
+    }
+
+    fn iter(&self) -> std::io::Result<Box<dyn Iterator<Item = (String, Vec<SenderRecord>)> + '_>> {
+        Ok(Box::new(self.data.iter().map(|(k, v)| (k.clone(), v.clone()))))
+    }
+
+    fn recipients_of(&self, sender: &str) -> std::io::Result<Vec<(String, SenderRecord)>> {
+        let keys = match self.by_sender.get(sender) {
+            Some(keys) => keys,
+            None => return Ok(Vec::new()),
+        };
+        Ok(keys
+            .iter()
+            .filter_map(|key| {
+                let record = self.data.get(key)?.iter().find(|r| r.sender == sender)?;
+                Some((key.clone(), record.clone()))
+            })
+            .collect())
+    }
+
+    fn clear(&mut self) {
+        self.data.clear();
+        self.by_sender.clear();
+        self.pending.push(JournalEvent::Clear);
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.flush_pending()?;
+        if self.journal_len >= self.compact_threshold {
+            self.compact()?;
+        }
+        Ok(())
+    }
+
+    fn len(&self) -> std::io::Result<usize> {
+        Ok(self.data.len())
+    }
+}
+
+/// A simple wrapper for serialization/deserialization to/from JSON.
+///
+/// Files written before timestamps were tracked hold bare sender strings;
+/// they still load (see `SenderRecord`) and are rewritten in the new form on
+/// the next snapshot.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct PersistData(pub(crate) HashMap<String, Vec<SenderRecord>>);
+
+/// Load data from the JSON file on disk into a `HashMap`, replaying any
+/// journal events recorded since that snapshot was written.
+///
+/// A missing file is an empty store; a file that cannot be read or parsed,
+/// or a corrupt journal, is an error, so a truncated snapshot is never
+/// mistaken for no data.
+///
+/// Marked `pub` so integration tests (and main) can call it.
+pub fn load_data(file_path: &str) -> std::io::Result<HashMap<String, Vec<SenderRecord>>> {
+    let mut data = load_snapshot(file_path)?;
+    journal::replay(&journal::journal_path(file_path), &mut data)?;
+    Ok(data)
+}
+
+/// Load only the JSON snapshot, ignoring the journal.
+fn load_snapshot(file_path: &str) -> std::io::Result<HashMap<String, Vec<SenderRecord>>> {
+    let json_str = match fs::read_to_string(file_path) {
+        Ok(json_str) => json_str,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => return Err(e),
+    };
+    serde_json::from_str::<PersistData>(&json_str)
+        .map(|parsed| parsed.0)
+        .map_err(|e| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{} is corrupt: {}", file_path, e),
+            )
+        })
+}
+
+/// Save the current store to disk as JSON.
+///
+/// The data is written to a temporary file next to `file_path`, synced, and
+/// renamed over the original, so readers only ever see the old or the new
+/// snapshot in full.
+///
+/// Marked `pub` so integration tests (and main) can call it.
+pub fn save_data(file_path: &str, data: &HashMap<String, Vec<SenderRecord>>) -> std::io::Result<()> {
+    let json_str = serde_json::to_string_pretty(&PersistData(data.clone()))
+        .map_err(std::io::Error::other)?;
+    write_atomic(Path::new(file_path), json_str.as_bytes())
+}
+
+/// Replaces `path` with `contents` via temp file + fsync + rename.
+pub(crate) fn write_atomic(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+
+    let mut file = fs::File::create(&tmp_path)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    drop(file);
+
+    fs::rename(&tmp_path, path)?;
+    sync_parent_dir(path)
+}
+
+/// Makes a rename durable by syncing the directory that holds `path`.
+#[cfg(unix)]
+fn sync_parent_dir(path: &Path) -> std::io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    fs::File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_parent_dir(_path: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Returns the most recent archive in `dir` whose name matches `pattern`
+/// (see `Config::archive_pattern`).
+///
+/// Archive names embed a `YYYYMMDDHHMMSS` timestamp, so the newest one is
+/// the one with the greatest timestamp.
+pub fn newest_backup(dir: &str, pattern: &str) -> Option<PathBuf> {
+    let (prefix, suffix) = pattern.split_once(TIMESTAMP_PLACEHOLDER)?;
+    fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let timestamp = name
+                .strip_prefix(prefix)?
+                .strip_suffix(suffix)?
+                .to_string();
+            Some((timestamp, entry.path()))
+        })
+        .max()
+        .map(|(_, path)| path)
+}
+
+/// Reads a compressed archive written by the `/clear` endpoint.
+pub fn load_backup(path: &Path) -> std::io::Result<HashMap<String, Vec<SenderRecord>>> {
+    let mut json_str = String::new();
+    GzDecoder::new(fs::File::open(path)?).read_to_string(&mut json_str)?;
+    serde_json::from_str::<PersistData>(&json_str)
+        .map(|parsed| parsed.0)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
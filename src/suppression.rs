@@ -0,0 +1,112 @@
+// src/suppression.rs
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use crate::storage::write_atomic;
+
+/// What put a number on the suppression list.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SuppressionSource {
+    /// `/suppress` or `/suppress/import`; also entries saved before sources
+    /// were recorded.
+    #[default]
+    Api,
+    /// An opt-out reply to `/inbound`, which an opt-in reply may lift.
+    Inbound,
+}
+
+/// Why and when a number was opted out.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Suppression {
+    pub suppressed_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    #[serde(default)]
+    pub source: SuppressionSource,
+}
+
+/// Numbers that must never be contacted, kept apart from the main store so
+/// that `/clear` and the storage engine do not affect them.
+///
+/// Lists are small and change rarely, so `flush` rewrites the whole file.
+#[derive(Debug, Default)]
+pub struct SuppressionList {
+    /// Where the list is persisted; `None` keeps it in memory only.
+    path: Option<String>,
+    entries: HashMap<String, Suppression>,
+    /// Whether `entries` has changed since it was last written, so that a
+    /// failed flush is retried by the next one.
+    dirty: bool,
+}
+
+/// The suppression list shared by the routes of one namespace.
+pub type Suppressions = Arc<Mutex<SuppressionList>>;
+
+impl SuppressionList {
+    /// An empty list that is never written to disk.
+    pub fn in_memory() -> Self {
+        SuppressionList::default()
+    }
+
+    /// Loads the list at `path`; a missing file is an empty list.
+    pub fn open(path: impl Into<String>) -> std::io::Result<Self> {
+        let path = path.into();
+        let entries = match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).map_err(std::io::Error::other)?,
+            Err(e) if e.kind() == ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(SuppressionList { path: Some(path), entries, dirty: false })
+    }
+
+    pub fn get(&self, number: &str) -> Option<&Suppression> {
+        self.entries.get(number)
+    }
+
+    /// Suppresses `number`. Returns `false`, keeping the original entry, if
+    /// it already was.
+    pub fn suppress(&mut self, number: String, reason: Option<String>, source: SuppressionSource, at: DateTime<Utc>) -> bool {
+        if self.entries.contains_key(&number) {
+            return false;
+        }
+        self.entries.insert(number, Suppression { suppressed_at: at, reason, source });
+        self.dirty = true;
+        true
+    }
+
+    /// Lifts the suppression of `number`, returning it if there was one.
+    pub fn unsuppress(&mut self, number: &str) -> Option<Suppression> {
+        let removed = self.entries.remove(number);
+        self.dirty |= removed.is_some();
+        removed
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Writes the list to its file, if it has one and it changed since the
+    /// last successful flush.
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        let path = match &self.path {
+            Some(path) if self.dirty => path,
+            _ => return Ok(()),
+        };
+        let json = serde_json::to_string_pretty(&self.entries).map_err(std::io::Error::other)?;
+        write_atomic(Path::new(path), json.as_bytes())?;
+        self.dirty = false;
+        Ok(())
+    }
+}
@@ -0,0 +1,144 @@
+// src/validation.rs
+
+use phonenumber::Mode;
+use serde::Serialize;
+
+use crate::config::Config;
+use crate::normalize::{normalize, Role};
+
+/// Why a phone number field was rejected, as reported in the `code` field of
+/// an error reply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// The field is absent (or `null`).
+    MissingField,
+    /// The field is present but not a JSON string.
+    NotAString,
+    /// The converted number does not have exactly 10 digits.
+    InvalidLength,
+    /// The area code (first digit) is 0 or 1.
+    InvalidAreaCode,
+    /// The exchange (fourth digit) is 0 or 1.
+    InvalidExchange,
+    /// In `e164` mode, the number could not be parsed or is not a valid
+    /// number for its country.
+    InvalidNumber,
+    /// The number contains letters and `vanity_letters` is off.
+    ContainsLetters,
+    /// A sender is a 5–6 digit short code and `short_code_senders` is off.
+    ShortCodeNotAllowed,
+}
+
+/// A rejected phone number field.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationError {
+    /// The request field, e.g. `key` or `val`.
+    pub field: String,
+    pub code: ErrorCode,
+    /// The number after normalization, when there was one.
+    pub normalized: Option<String>,
+}
+
+impl ValidationError {
+    /// A human-readable description of the problem.
+    pub fn message(&self) -> String {
+        let normalized = self.normalized.as_deref().unwrap_or_default();
+        match self.code {
+            ErrorCode::MissingField => format!("Missing field '{}'.", self.field),
+            ErrorCode::NotAString => format!("Field '{}' must be a string.", self.field),
+            ErrorCode::InvalidLength => format!(
+                "'{}' must be 10 digits after conversion (got {} digits).",
+                self.field,
+                normalized.len()
+            ),
+            ErrorCode::InvalidAreaCode => format!("'{}' has an area code starting with 0 or 1.", self.field),
+            ErrorCode::InvalidExchange => format!("'{}' has an exchange starting with 0 or 1.", self.field),
+            ErrorCode::InvalidNumber => format!("'{}' is not a valid phone number.", self.field),
+            ErrorCode::ContainsLetters => format!("'{}' contains letters, which are not translated.", self.field),
+            ErrorCode::ShortCodeNotAllowed => format!("'{}' is a short code, which is not accepted as a sender.", self.field),
+        }
+    }
+
+    /// The error reply body.
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "status": "error",
+            "code": self.code,
+            "field": self.field,
+            "message": self.message(),
+            "normalized": self.normalized
+        })
+    }
+}
+
+/// Checks that a converted number is a valid 10-digit NANP number: area
+/// code and exchange must not start with 0 or 1.
+pub fn check_nanp(number: &str) -> Result<(), ErrorCode> {
+    let digits = number.as_bytes();
+    if digits.len() != 10 || !digits.iter().all(u8::is_ascii_digit) {
+        return Err(ErrorCode::InvalidLength);
+    }
+    if digits[0] < b'2' {
+        return Err(ErrorCode::InvalidAreaCode);
+    }
+    if digits[3] < b'2' {
+        return Err(ErrorCode::InvalidExchange);
+    }
+    Ok(())
+}
+
+/// Parses `raw` as a number of any country and formats it as E.164.
+/// Numbers without a country code are read as numbers of `country`.
+pub fn to_e164(raw: &str, country: Option<phonenumber::country::Id>) -> Result<String, Option<String>> {
+    let number = phonenumber::parse(country, raw).map_err(|_| None)?;
+    let e164 = number.format().mode(Mode::E164).to_string();
+    if phonenumber::is_valid(&number) {
+        Ok(e164)
+    } else {
+        Err(Some(e164))
+    }
+}
+
+/// Normalizes `raw` (see `normalize`) and validates the result.
+pub fn phone_number(field: &str, role: Role, raw: &str, config: &Config) -> Result<String, ValidationError> {
+    normalize(raw, role, config).result.map_err(|(code, normalized)| ValidationError {
+        field: field.to_string(),
+        code,
+        normalized,
+    })
+}
+
+/// Reads a phone number from a URL path segment, which warp hands over still
+/// percent-encoded.
+pub fn path_number(field: &str, role: Role, segment: &str, config: &Config) -> Result<String, ValidationError> {
+    let raw = percent_encoding::percent_decode_str(segment).decode_utf8_lossy();
+    phone_number(field, role, &raw, config)
+}
+
+/// Reads `field` from a JSON request body as a phone number.
+pub fn phone_field(body: &serde_json::Value, field: &str, role: Role, config: &Config) -> Result<String, ValidationError> {
+    let error = |code| ValidationError { field: field.to_string(), code, normalized: None };
+    match &body[field] {
+        serde_json::Value::Null => Err(error(ErrorCode::MissingField)),
+        serde_json::Value::String(raw) => phone_number(field, role, raw, config),
+        _ => Err(error(ErrorCode::NotAString)),
+    }
+}
+
+/// Reads the `key` (recipient) and `val` (sender) of an `/add` or `/addmulti` body.
+pub fn key_and_val(body: &serde_json::Value, config: &Config) -> Result<(String, String), ValidationError> {
+    Ok((phone_field(body, "key", Role::Recipient, config)?, phone_field(body, "val", Role::Sender, config)?))
+}
+
+/// Reads the `key` and optional `val` of a `/check` request.
+pub fn key_and_optional_val(
+    body: &serde_json::Value,
+    config: &Config,
+) -> Result<(String, Option<String>), ValidationError> {
+    let val = match &body["val"] {
+        serde_json::Value::Null => None,
+        _ => Some(phone_field(body, "val", Role::Sender, config)?),
+    };
+    Ok((phone_field(body, "key", Role::Recipient, config)?, val))
+}